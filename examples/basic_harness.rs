@@ -1,33 +1,61 @@
 extern crate harness;
 
+use std::fmt::Write;
 use std::io::Read;
 
-fn foo() -> Result<(), &'static str> {
-    println!("Called foo!");
+use harness::{Args, Command, CommandResult, Error, Outcome};
+
+fn foo(_args: &Args, out: &mut dyn Write) -> CommandResult {
+    writeln!(out, "Called foo!")?;
     Ok(())
 }
 
-fn bar() -> Result<(), &'static str> {
-    println!("Called bar!");
-    Err("bar doesn't work")
+fn bar(_args: &Args, out: &mut dyn Write) -> CommandResult {
+    writeln!(out, "Called bar!")?;
+    Err("bar doesn't work".into())
 }
 
-fn quit() -> Result<(), &'static str> {
-    std::process::exit(0)
+fn echo(args: &Args, out: &mut dyn Write) -> CommandResult {
+    writeln!(out, "{}", args.join(" "))?;
+    Ok(())
 }
 
+fn quit(_: &mut (), _args: &Args, _out: &mut dyn Write) -> Result<Outcome, Error> {
+    Ok(Outcome::EndSession)
+}
+
+static COMMANDS: [Command; 4] = [Command::new("foo", "Foo's the frobble", foo),
+                                 Command::new("bar", "Bar's the frobble", bar),
+                                 Command::new("echo", "Prints its arguments", echo),
+                                 Command::with_outcome("quit", "Exits the program", quit)];
+
 fn main() {
     println!("Command line harness example\r\n");
-    let mut h = harness::Harness::new(std::io::stdout());
-    h.add_command("foo", "Foo's the frobble", foo);
-    h.add_command("bar", "Bar's the frobble", bar);
-    h.add_command("quit", "Exit's the program", quit);
+    let mut count = 0;
+    let writer = harness::IoWriter::new(std::io::stdout());
+    let mut h = harness::Harness::with_commands(writer, &COMMANDS);
+    h.add_command("count", "Counts how often it is called", |_: &Args, out: &mut dyn Write| {
+        count += 1;
+        writeln!(out, "Called {} times", count)?;
+        Ok(())
+    });
     h.prompt().unwrap();
     loop {
         let mut buf = [0u8; 1];
-        if let Ok(n) = std::io::stdin().read(&mut buf) {
-            for b in buf.iter().take(n) {
-                h.receive_and_print(*b).unwrap();
+        match std::io::stdin().read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                match h.receive_and_print(buf[0]) {
+                    Ok(Outcome::EndSession) => break,
+                    Ok(_) => {}
+                    Err(_) => {
+                        match h.writer_mut().take_error() {
+                            Some(e) => eprintln!("Can't write to stdout: {}", e),
+                            None => eprintln!("Can't write to stdout"),
+                        }
+                        break;
+                    }
+                }
             }
         }
     }
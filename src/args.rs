@@ -0,0 +1,500 @@
+use core::ops::Deref;
+
+use crate::param::{self, Param};
+use crate::vars::{self, Scope};
+use crate::Error;
+
+/// The most words a command line can be split into, including the command
+/// name.
+pub const MAX_ARGS: usize = 16;
+
+/// The arguments given to a command. The command name itself is available
+/// through `name()`, and the remaining words can be accessed like a slice.
+///
+/// If the command has parameters (see `Command::with_params`), the arguments
+/// have already been checked against them, any defaults have been filled in,
+/// and the arguments can also be fetched by parameter name, e.g.
+/// `args.int("duty")`.
+pub struct Args<'a> {
+    name: &'a str,
+    args: &'a [&'a str],
+    params: &'a [Param<'a>],
+}
+
+impl<'a> Args<'a> {
+    pub fn new(name: &'a str, args: &'a [&'a str]) -> Args<'a> {
+        Args { name, args, params: &[] }
+    }
+
+    pub(crate) fn with_params(mut self, params: &'a [Param<'a>]) -> Args<'a> {
+        self.params = params;
+        self
+    }
+
+    /// The name the command was invoked with.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// The argument given for the named parameter, if there was one.
+    pub fn get(&self, param: &str) -> Option<&'a str> {
+        let idx = self.params.iter().position(|p| p.name() == param)?;
+        self.args.get(idx).copied()
+    }
+
+    /// The named integer parameter.
+    pub fn int(&self, param: &str) -> Option<i64> {
+        self.get(param)?.parse().ok()
+    }
+
+    /// The named floating-point parameter.
+    pub fn float(&self, param: &str) -> Option<f64> {
+        self.get(param)?.parse().ok()
+    }
+
+    /// The named boolean parameter.
+    pub fn bool(&self, param: &str) -> Option<bool> {
+        param::parse_bool(self.get(param)?)
+    }
+}
+
+impl<'a> Deref for Args<'a> {
+    type Target = [&'a str];
+
+    fn deref(&self) -> &[&'a str] {
+        self.args
+    }
+}
+
+/// Splits `src` into whitespace separated words, writing the unescaped words
+/// into `dst` and storing each one in `argv`. Returns the number of words.
+/// At most `MAX_ARGS` words are supported.
+///
+/// Text inside double quotes is kept together, and may contain backslash
+/// escapes. Text inside single quotes is taken literally. Outside of quotes, a
+/// backslash causes the following character to be taken literally.
+///
+/// `dst` must be at least as long as `src`.
+pub fn tokenize<'d>(src: &str,
+                    dst: &'d mut [u8],
+                    argv: &mut [&'d str])
+                    -> Result<usize, Error> {
+    split(src, dst, argv, None)
+}
+
+/// Like `tokenize`, but with variables (`$name` or `${name}`) outside single
+/// quotes replaced by their values from `scope`, if given. A value is never
+/// split into several words, and a word made only of variables that aren't
+/// set is left out altogether. `dst` must be long enough to hold the
+/// expanded words, or `Error::LineTooLong` is returned.
+pub(crate) fn split<'d>(src: &str,
+                        dst: &'d mut [u8],
+                        argv: &mut [&'d str],
+                        scope: Option<&Scope>)
+                        -> Result<usize, Error> {
+    let max = argv.len().min(MAX_ARGS);
+    let mut spans = [(0, 0); MAX_ARGS];
+    let mut count = 0;
+    let mut out = Unescaped { dst, len: 0 };
+    let bytes = src.as_bytes();
+    let mut i = 0;
+    let mut in_word = false;
+    // Has the word got anything in it other than variables?
+    let mut literal = false;
+    let mut start = 0;
+    while let Some(&b) = bytes.get(i) {
+        i += 1;
+        match b {
+            b' ' | b'\t' => {
+                if in_word && (literal || out.len > start) {
+                    spans[count] = (start, out.len);
+                    count += 1;
+                }
+                in_word = false;
+                continue;
+            }
+            _ if !in_word => {
+                if count == max {
+                    return Err(Error::TooManyArguments);
+                }
+                in_word = true;
+                literal = false;
+                start = out.len;
+            }
+            _ => {}
+        }
+        match b {
+            b'$' if scope.is_some() => {
+                match expand(&src[i..], scope, &mut out)? {
+                    0 => {
+                        out.push(b'$')?;
+                        literal = true;
+                    }
+                    used => i += used,
+                }
+                continue;
+            }
+            b'"' => {
+                loop {
+                    match bytes.get(i) {
+                        Some(b'"') => break,
+                        Some(b'\\') => {
+                            match bytes.get(i + 1) {
+                                Some(&c) => out.push(c)?,
+                                None => return Err(Error::UnterminatedQuote),
+                            }
+                            i += 1;
+                        }
+                        Some(b'$') if scope.is_some() => {
+                            match expand(&src[i + 1..], scope, &mut out)? {
+                                0 => out.push(b'$')?,
+                                used => i += used,
+                            }
+                        }
+                        Some(&c) => out.push(c)?,
+                        None => return Err(Error::UnterminatedQuote),
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            b'\'' => {
+                loop {
+                    match bytes.get(i) {
+                        Some(b'\'') => break,
+                        Some(&c) => out.push(c)?,
+                        None => return Err(Error::UnterminatedQuote),
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            b'\\' => {
+                match bytes.get(i) {
+                    Some(&c) => out.push(c)?,
+                    None => return Err(Error::TrailingBackslash),
+                }
+                i += 1;
+            }
+            c => out.push(c)?,
+        }
+        literal = true;
+    }
+    if in_word && (literal || out.len > start) {
+        spans[count] = (start, out.len);
+        count += 1;
+    }
+    // Only ASCII quotes and backslashes have been removed, and whole values
+    // added, so every word is still valid UTF-8.
+    let dst: &'d [u8] = out.dst;
+    for (arg, &(start, end)) in argv.iter_mut().zip(spans[..count].iter()) {
+        *arg = try_utf8(&dst[start..end])?;
+    }
+    Ok(count)
+}
+
+/// Writes the value of the variable referred to at the start of `src`,
+/// which follows a `$`. Returns how many bytes of `src` were used, which is
+/// 0 if they don't refer to a variable.
+fn expand(src: &str, scope: Option<&Scope>, out: &mut Unescaped) -> Result<usize, Error> {
+    match (scope, vars::reference(src)) {
+        (Some(scope), Some((name, used))) => {
+            scope.get(name).unwrap_or("").bytes().try_for_each(|b| out.push(b))?;
+            Ok(used)
+        }
+        _ => Ok(0),
+    }
+}
+
+/// The words of a command line, as they are written out.
+struct Unescaped<'d> {
+    dst: &'d mut [u8],
+    len: usize,
+}
+
+impl<'d> Unescaped<'d> {
+    fn push(&mut self, b: u8) -> Result<(), Error> {
+        *self.dst.get_mut(self.len).ok_or(Error::LineTooLong)? = b;
+        self.len += 1;
+        Ok(())
+    }
+}
+
+/// Where the commands start in `alias <name> = <commands>`: just after the
+/// first `=` that is a word by itself, outside quotes. Returns `None` if
+/// there isn't one.
+pub(crate) fn after_equals(line: &str) -> Option<usize> {
+    let bytes = line.as_bytes();
+    let mut quote = None;
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match (quote, bytes[i]) {
+            (Some(b'"'), b'\\') | (None, b'\\') => i += 1,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, c @ (b'"' | b'\'')) => quote = Some(c),
+            (None, b' ' | b'\t') => {
+                if &line[start..i] == "=" {
+                    return Some(i);
+                }
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    (&line[start..] == "=").then_some(line.len())
+}
+
+/// The commands that a macro runs, from `src`, which follows the `=` in
+/// `alias <name> = <commands>`. They are kept as they were typed, so that
+/// they are split, and their variables expanded, when the macro runs. If
+/// they are a single word, such as `'led $1 on'`, it is unquoted.
+pub(crate) fn body<'d>(src: &'d str, dst: &'d mut [u8]) -> Result<&'d str, Error> {
+    let src = src.trim_matches([' ', '\t']);
+    let mut words = [""; 2];
+    match split(src, dst, &mut words, None) {
+        Ok(1) => Ok(words[0]),
+        Ok(_) | Err(Error::TooManyArguments) => Ok(src),
+        Err(e) => Err(e),
+    }
+}
+
+/// How a command in a chain (see `chain`) depends on the one before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Link {
+    /// Runs regardless, after `;` or at the start of the line.
+    Always,
+    /// Runs only if the last command to run succeeded, after `&&`.
+    IfOk,
+    /// Runs only if the last command to run failed, after `||`.
+    IfFailed,
+}
+
+impl Link {
+    /// The operator before a command linked this way.
+    fn operator(self) -> &'static str {
+        match self {
+            Link::Always => ";",
+            Link::IfOk => "&&",
+            Link::IfFailed => "||",
+        }
+    }
+}
+
+/// Splits a command line into the commands chained together with `;`, `&&`
+/// and `||`. These are taken literally inside quotes, or after a backslash.
+pub(crate) fn chain(line: &str) -> Chain<'_> {
+    Chain { rest: Some(line), link: Link::Always }
+}
+
+/// Checks that every `;`, `&&` and `||` in a command line has a command
+/// before it, and that every `&&` and `||` has one after it. A line may end
+/// with `;`.
+pub(crate) fn check_chain(line: &str) -> Result<(), Error> {
+    let mut commands = chain(line).peekable();
+    while let Some((link, command)) = commands.next() {
+        if !command.trim_matches([' ', '\t']).is_empty() {
+            continue;
+        }
+        match commands.peek() {
+            Some((next, _)) => return Err(Error::MissingCommand { before: true, operator: next.operator() }),
+            None if link != Link::Always => {
+                return Err(Error::MissingCommand { before: false, operator: link.operator() })
+            }
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// The commands in a command line, each with how it depends on the one
+/// before it.
+pub(crate) struct Chain<'l> {
+    rest: Option<&'l str>,
+    link: Link,
+}
+
+impl<'l> Iterator for Chain<'l> {
+    type Item = (Link, &'l str);
+
+    fn next(&mut self) -> Option<(Link, &'l str)> {
+        let rest = self.rest?;
+        let bytes = rest.as_bytes();
+        let mut quote = None;
+        let mut i = 0;
+        while i < bytes.len() {
+            let (link, width) = match (quote, bytes[i], bytes.get(i + 1)) {
+                (Some(b'"'), b'\\', _) | (None, b'\\', _) => {
+                    i += 2;
+                    continue;
+                }
+                (Some(q), c, _) => {
+                    if c == q {
+                        quote = None;
+                    }
+                    i += 1;
+                    continue;
+                }
+                (None, c @ (b'"' | b'\''), _) => {
+                    quote = Some(c);
+                    i += 1;
+                    continue;
+                }
+                (None, b';', _) => (Link::Always, 1),
+                (None, b'&', Some(b'&')) => (Link::IfOk, 2),
+                (None, b'|', Some(b'|')) => (Link::IfFailed, 2),
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let command = (self.link, &rest[..i]);
+            self.rest = Some(&rest[i + width..]);
+            self.link = link;
+            return Some(command);
+        }
+        self.rest = None;
+        Some((self.link, rest))
+    }
+}
+
+fn try_utf8(bytes: &[u8]) -> Result<&str, Error> {
+    core::str::from_utf8(bytes).map_err(|_| Error::InvalidUtf8)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{after_equals, body, chain, check_chain, split as expand, tokenize, Args, Link, MAX_ARGS};
+    use crate::vars::{Scope, Variables};
+    use crate::{Error, Param};
+
+    fn split(line: &str) -> Result<Vec<String>, Error> {
+        let mut buf = vec![0u8; line.len()];
+        let mut argv = [""; MAX_ARGS];
+        let argc = tokenize(line, &mut buf, &mut argv)?;
+        Ok(argv[..argc].iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn whitespace() {
+        assert_eq!(split("  led 3\ton  ").unwrap(), ["led", "3", "on"]);
+        assert_eq!(split("").unwrap(), Vec::<String>::new());
+        assert_eq!(split("   ").unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(split("say \"hello world\" 'a \\b'").unwrap(),
+                   ["say", "hello world", "a \\b"]);
+        assert_eq!(split("x\"y z\"w").unwrap(), ["xy zw"]);
+        assert_eq!(split("echo \"\" ''").unwrap(), ["echo", "", ""]);
+        assert_eq!(split("echo \"a \\\" b\"").unwrap(), ["echo", "a \" b"]);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(split("a\\ b c").unwrap(), ["a b", "c"]);
+        assert_eq!(split("caf\u{e9} \\\u{e9}").unwrap(), ["caf\u{e9}", "\u{e9}"]);
+    }
+
+    fn expanded(line: &str, failed: bool) -> Result<Vec<String>, Error> {
+        let mut variables = Variables::new("variable");
+        variables.set("addr", "0x4000").unwrap();
+        variables.set("msg", "hello world").unwrap();
+        variables.set("empty", "").unwrap();
+        let scope = Scope { variables: &variables, failed, args: &["first"] };
+        let mut buf = [0u8; 40];
+        let mut argv = [""; MAX_ARGS];
+        let argc = expand(line, &mut buf, &mut argv, Some(&scope))?;
+        Ok(argv[..argc].iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(expanded("peek $addr ${addr}0", false).unwrap(), ["peek", "0x4000", "0x40000"]);
+        assert_eq!(expanded("say $msg \"[$msg]\" '$msg' \\$msg", false).unwrap(),
+                   ["say", "hello world", "[hello world]", "$msg", "$msg"]);
+        assert_eq!(expanded("x $missing $empty \"$missing\" y", false).unwrap(), ["x", "", "y"]);
+        assert_eq!(expanded("status $? ${?}", true).unwrap(), ["status", "1", "${?}"]);
+        assert_eq!(expanded("$ $/ \"$\" ${addr", false).unwrap(), ["$", "$/", "$", "${addr"]);
+        assert_eq!(expanded("$1 $2 ${1}0", false).unwrap(), ["first", "first0"]);
+        assert_eq!(expanded("$msg$msg$msg$msg", false), Err(Error::LineTooLong));
+        assert_eq!(split("echo $addr").unwrap(), ["echo", "$addr"]);
+    }
+
+    #[test]
+    fn by_name() {
+        let params = [Param::int("count"), Param::float("volts"), Param::bool("on"), Param::string("label")];
+        let args = Args::new("x", &["-3", "2.5", "off"]).with_params(&params);
+        assert_eq!(args.int("count"), Some(-3));
+        assert_eq!(args.float("volts"), Some(2.5));
+        assert_eq!(args.bool("on"), Some(false));
+        assert_eq!(args.get("label"), None);
+        assert_eq!(args.get("missing"), None);
+        assert_eq!(args.int("volts"), None);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(split("say \"hello"), Err(Error::UnterminatedQuote));
+        assert_eq!(split("say 'hello"), Err(Error::UnterminatedQuote));
+        assert_eq!(split("say hello\\"), Err(Error::TrailingBackslash));
+        assert_eq!(split("0 1 2 3 4 5 6 7 8 9 a b c d e f").unwrap().len(), 16);
+        assert_eq!(split("0 1 2 3 4 5 6 7 8 9 a b c d e f g"), Err(Error::TooManyArguments));
+    }
+
+    #[test]
+    fn chains() {
+        let split = |line| chain(line).collect::<Vec<_>>();
+        assert_eq!(split("reset; sleep 100 ;status"),
+                   [(Link::Always, "reset"), (Link::Always, " sleep 100 "), (Link::Always, "status")]);
+        assert_eq!(split("a && b || c"), [(Link::Always, "a "), (Link::IfOk, " b "), (Link::IfFailed, " c")]);
+        assert_eq!(split("a &b |c"), [(Link::Always, "a &b |c")]);
+        assert_eq!(split("a;"), [(Link::Always, "a"), (Link::Always, "")]);
+        assert_eq!(split(""), [(Link::Always, "")]);
+    }
+
+    #[test]
+    fn empty_commands() {
+        let missing = |before, operator| Err(Error::MissingCommand { before, operator });
+        assert_eq!(check_chain("a; b && c || d;"), Ok(()));
+        assert_eq!(check_chain(""), Ok(()));
+        assert_eq!(check_chain(" \t"), Ok(()));
+        assert_eq!(check_chain("&& show"), missing(true, "&&"));
+        assert_eq!(check_chain("a; || b"), missing(true, "||"));
+        assert_eq!(check_chain(";;show x;;"), missing(true, ";"));
+        assert_eq!(check_chain("show x;;"), missing(true, ";"));
+        assert_eq!(check_chain("show x &&"), missing(false, "&&"));
+        assert_eq!(check_chain("a || "), missing(false, "||"));
+        assert_eq!(check_chain("echo '' && echo ';'"), Ok(()));
+    }
+
+    #[test]
+    fn definitions() {
+        assert_eq!(after_equals("alias f = show 3 'with spaces'"), Some(9));
+        assert_eq!(after_equals("alias f\t=\tshow"), Some(9));
+        assert_eq!(after_equals("alias f ="), Some(9));
+        assert_eq!(after_equals("alias f '=' x = y"), Some(15));
+        assert_eq!(after_equals("alias f \\= x"), None);
+        assert_eq!(after_equals("alias f=x \"a = b\""), None);
+        assert_eq!(after_equals("alias f"), None);
+        let mut buf = [0u8; 32];
+        assert_eq!(body(" show 3 'with spaces' ", &mut buf), Ok("show 3 'with spaces'"));
+        assert_eq!(body("echo 'a;b'", &mut buf), Ok("echo 'a;b'"));
+        assert_eq!(body(" \"show $1 on\"", &mut buf), Ok("show $1 on"));
+        assert_eq!(body("status", &mut buf), Ok("status"));
+        assert_eq!(body("", &mut buf), Ok(""));
+        assert_eq!(body("show 'x", &mut buf), Err(Error::UnterminatedQuote));
+    }
+
+    #[test]
+    fn quoted_chains() {
+        let split = |line| chain(line).map(|(_, command)| command).collect::<Vec<_>>();
+        assert_eq!(split("echo 'a;b' \"c && d\" e\\;f"), ["echo 'a;b' \"c && d\" e\\;f"]);
+        assert_eq!(split("echo \"\\\";\"; x"), ["echo \"\\\";\"", " x"]);
+        assert_eq!(split("echo 'a\\';b"), ["echo 'a\\'", "b"]);
+        assert_eq!(split("echo \"a;b"), ["echo \"a;b"]);
+    }
+}
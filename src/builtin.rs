@@ -0,0 +1,127 @@
+use crate::Param;
+
+const SOURCE: [Param; 1] = [Param::string("script").help("The name of the script")];
+const SET: [Param; 2] = [Param::string("name").help("The name of the variable"),
+                         Param::string("value").help("What it is set to")];
+const UNSET: [Param; 1] = [Param::string("name").help("The name of the variable")];
+/// The most times the `repeat` built-in command runs a command. Nothing can
+/// interrupt it, so this stops it holding up the main loop for too long.
+pub const MAX_REPEAT: i64 = 1000;
+
+const REPEAT: [Param; 2] = [Param::int_range("count", 0, MAX_REPEAT).help("How many times to run the command"),
+                            Param::string("command").help("The command to run, and its arguments")];
+const WATCH: [Param; 2] = [Param::float_range("seconds", 0.1, 3600.0).help("How often to run the command"),
+                           Param::string("command").help("The command to run, and its arguments")];
+
+/// A command that the `Harness` runs itself.
+///
+/// `help` and `history` are available unless turned off with
+/// `Harness::set_builtins`, which can also turn the others on. A command
+/// registered with the same name as a built-in replaces it, and
+/// `Command::builtin` makes a built-in available under another name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Builtin {
+    /// Lists the commands, or describes the one named by its arguments.
+    Help,
+    /// Prints its arguments.
+    Echo,
+    /// Clears the screen.
+    Clear,
+    /// Prints the version given to `Harness::set_version`.
+    Version,
+    /// Prints the command lines in the history.
+    History,
+    /// Ends the session, by returning `Outcome::EndSession`.
+    Exit,
+    /// Runs a script given to `Harness::set_scripts`.
+    Source,
+    /// Sets a session variable (see `Harness::set_variable`).
+    Set,
+    /// Removes a session variable.
+    Unset,
+    /// Lists the session variables and their values.
+    Env,
+    /// Defines a macro, as in `alias calib = "adc reset; adc cal 3"`, shows
+    /// one, or lists them all.
+    Alias,
+    /// Runs a command a number of times, up to `MAX_REPEAT`, stopping if it
+    /// fails.
+    Repeat,
+    /// Runs a command every so often, clearing the screen first, until a
+    /// key is pressed. Needs a clock (see `Harness::set_clock`). It must be
+    /// the only command on a line typed at the prompt.
+    Watch,
+}
+
+impl Builtin {
+    /// Every built-in command.
+    pub const ALL: [Builtin; 13] = [Builtin::Help,
+                                    Builtin::Echo,
+                                    Builtin::Clear,
+                                    Builtin::Version,
+                                    Builtin::History,
+                                    Builtin::Exit,
+                                    Builtin::Source,
+                                    Builtin::Set,
+                                    Builtin::Unset,
+                                    Builtin::Env,
+                                    Builtin::Alias,
+                                    Builtin::Repeat,
+                                    Builtin::Watch];
+
+    /// The name the command has unless given another.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Builtin::Help => "help",
+            Builtin::Echo => "echo",
+            Builtin::Clear => "clear",
+            Builtin::Version => "version",
+            Builtin::History => "history",
+            Builtin::Exit => "exit",
+            Builtin::Source => "source",
+            Builtin::Set => "set",
+            Builtin::Unset => "unset",
+            Builtin::Env => "env",
+            Builtin::Alias => "alias",
+            Builtin::Repeat => "repeat",
+            Builtin::Watch => "watch",
+        }
+    }
+
+    pub const fn help_text(self) -> &'static str {
+        match self {
+            Builtin::Help => "Lists the commands, or describes one",
+            Builtin::Echo => "Prints its arguments",
+            Builtin::Clear => "Clears the screen",
+            Builtin::Version => "Prints the version",
+            Builtin::History => "Lists the commands entered so far",
+            Builtin::Exit => "Ends the session",
+            Builtin::Source => "Runs a script",
+            Builtin::Set => "Sets a variable",
+            Builtin::Unset => "Removes a variable",
+            Builtin::Env => "Lists the variables",
+            Builtin::Alias => "Defines a macro, as in: alias <name> = <commands>",
+            Builtin::Repeat => "Runs a command a number of times",
+            Builtin::Watch => "Runs a command every so often, until a key is pressed",
+        }
+    }
+
+    /// The arguments the command takes, if they can be checked.
+    pub(crate) const fn params(self) -> &'static [Param<'static>] {
+        match self {
+            Builtin::Source => &SOURCE,
+            Builtin::Set => &SET,
+            Builtin::Unset => &UNSET,
+            Builtin::Repeat => &REPEAT,
+            Builtin::Watch => &WATCH,
+            _ => &[],
+        }
+    }
+
+    /// Whether the command's last parameter is a command to run, which
+    /// takes up the rest of the arguments.
+    pub(crate) const fn runs_command(self) -> bool {
+        matches!(self, Builtin::Repeat | Builtin::Watch)
+    }
+}
@@ -0,0 +1,727 @@
+use core::fmt::{self, Write};
+
+use crate::param::{self, Param};
+use crate::Builtin;
+use crate::suggest::suggest;
+use crate::{Args, Error, Message, Suggestions, MAX_ARGS};
+
+/// What a command handler returns.
+pub type CommandResult = Result<(), Error>;
+
+/// What should happen once a command has run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Outcome {
+    /// Carry on reading commands.
+    #[default]
+    Continue,
+    /// The user is finished, e.g. the connection should be closed.
+    EndSession,
+    /// The session should start again from the beginning.
+    RestartSession,
+}
+
+/// A command handler that only needs its arguments and somewhere to write
+/// output.
+pub type CommandFn = fn(&Args, &mut dyn Write) -> CommandResult;
+
+/// A command handler that is also given the context passed to
+/// `Harness::receive_with`.
+pub type ContextFn<T> = fn(&mut T, &Args, &mut dyn Write) -> CommandResult;
+
+/// A command handler that is given the context, like a `ContextFn`, and
+/// also says what should happen next, e.g. that the session should end.
+pub type OutcomeFn<T> = fn(&mut T, &Args, &mut dyn Write) -> Result<Outcome, Error>;
+
+/// Offers possible values for the word being completed when Tab is pressed.
+/// It is given the words on the line before that one (including the command
+/// name), and should call the function it is given with each possible value.
+/// Values that don't match what has been typed so far are ignored, so there
+/// is no need to filter them.
+///
+/// ```
+/// use harness::Args;
+///
+/// fn complete_gpio(args: &Args, add: &mut dyn FnMut(&str)) {
+///     match args.len() {
+///         0 => ["get", "set"].iter().for_each(|s| add(s)),
+///         1 => ["PA0", "PA1", "PB0"].iter().for_each(|s| add(s)),
+///         _ => {}
+///     }
+/// }
+/// ```
+pub type CompleteFn = fn(&Args, &mut dyn FnMut(&str));
+
+/// A boxed closure handler. It is given the user context (see
+/// `Harness::receive_with`), the arguments from the command line, and the
+/// `Harness` writer to send any output to.
+#[cfg(feature = "std")]
+pub(crate) type BoxedHandler<'a, T> =
+    Box<dyn FnMut(&mut T, &Args, &mut dyn Write) -> Result<Outcome, Error> + 'a>;
+
+pub(crate) enum Handler<T> {
+    Fn(CommandFn),
+    Context(ContextFn<T>),
+    Outcome(OutcomeFn<T>),
+    /// An index into the `Harness`'s boxed closures.
+    #[cfg(feature = "std")]
+    Boxed(usize),
+    /// Not a command at all, but a group of subcommands.
+    Group,
+    /// Run by the `Harness` itself.
+    Builtin(Builtin),
+}
+
+impl<T> Clone for Handler<T> {
+    fn clone(&self) -> Handler<T> {
+        *self
+    }
+}
+
+impl<T> Copy for Handler<T> {}
+
+/// Represents a command that can be called. It has a function that is called when its name
+/// is entered at the command line. Any further words on the line are passed to the function
+/// as arguments.
+///
+/// Commands can be built in a `const` or `static` table and handed to
+/// `Harness::with_commands`, which needs no allocator:
+///
+/// ```
+/// use std::fmt::Write;
+/// use harness::{Args, Command, CommandResult};
+///
+/// fn hello(_args: &Args, out: &mut dyn Write) -> CommandResult {
+///     writeln!(out, "Hello!")?;
+///     Ok(())
+/// }
+///
+/// static COMMANDS: [Command; 1] = [Command::new("hello", "Says hello", hello)];
+///
+/// let mut h = harness::Harness::with_commands(String::new(), &COMMANDS);
+/// for b in b"hello\n".iter() {
+///     h.receive(*b);
+/// }
+/// assert_eq!(h.writer(), "Hello!\n");
+/// ```
+///
+/// Related commands can be gathered into a group with `Command::group`, so
+/// that `net ip set 10.0.0.2` runs the `set` command in the `ip` group in
+/// the `net` group.
+pub struct Command<'a, T = ()> {
+    pub(crate) name: &'a str,
+    pub(crate) help_text: &'a str,
+    pub(crate) handler: Handler<T>,
+    pub(crate) complete: Option<CompleteFn>,
+    /// The subcommands, if this is a group.
+    pub(crate) children: &'a [Command<'a, T>],
+    pub(crate) params: &'a [Param<'a>],
+    /// Shown by `help <command>`, along with `help_text`.
+    pub(crate) description: &'a str,
+    pub(crate) examples: &'a [&'a str],
+    /// The heading `help` lists this command under.
+    pub(crate) category: &'a str,
+    /// The names of the groups a command registered at run-time belongs to,
+    /// separated by spaces.
+    #[cfg(feature = "std")]
+    pub(crate) parent: &'a str,
+}
+
+impl<'a, T> Command<'a, T> {
+    const fn build(name: &'a str, help_text: &'a str, handler: Handler<T>) -> Command<'a, T> {
+        Command {
+            name,
+            help_text,
+            handler,
+            complete: None,
+            children: &[],
+            params: &[],
+            description: "",
+            examples: &[],
+            category: "",
+            #[cfg(feature = "std")]
+            parent: "",
+        }
+    }
+
+    pub const fn new(name: &'a str, help_text: &'a str, handler: CommandFn) -> Command<'a, T> {
+        Command::build(name, help_text, Handler::Fn(handler))
+    }
+
+    /// Creates a command whose handler is also given the context passed to
+    /// `Harness::receive_with`.
+    pub const fn with_context(name: &'a str,
+                              help_text: &'a str,
+                              handler: ContextFn<T>)
+                              -> Command<'a, T> {
+        Command::build(name, help_text, Handler::Context(handler))
+    }
+
+    /// Creates a command whose handler says what should happen next, such
+    /// as ending the session. The outcome is returned by
+    /// `Harness::receive_with`.
+    ///
+    /// ```
+    /// use std::fmt::Write;
+    /// use harness::{Args, Command, Error, Outcome};
+    ///
+    /// fn logout(_: &mut (), _args: &Args, out: &mut dyn Write) -> Result<Outcome, Error> {
+    ///     writeln!(out, "Bye!")?;
+    ///     Ok(Outcome::EndSession)
+    /// }
+    ///
+    /// static COMMANDS: [Command; 1] = [Command::with_outcome("logout", "Logs out", logout)];
+    ///
+    /// let mut h = harness::Harness::with_commands(String::new(), &COMMANDS);
+    /// let mut outcome = Outcome::Continue;
+    /// for b in b"logout\n".iter() {
+    ///     outcome = h.receive_and_print(*b).unwrap();
+    /// }
+    /// assert_eq!(outcome, Outcome::EndSession);
+    /// assert_eq!(h.writer(), "Bye!\n");
+    /// ```
+    pub const fn with_outcome(name: &'a str,
+                              help_text: &'a str,
+                              handler: OutcomeFn<T>)
+                              -> Command<'a, T> {
+        Command::build(name, help_text, Handler::Outcome(handler))
+    }
+
+    /// Creates a group of commands. Entering the group's name followed by the
+    /// name of one of its children runs that child, and `help <group>` lists
+    /// the children. Groups can contain other groups.
+    ///
+    /// ```
+    /// use std::fmt::Write;
+    /// use harness::{Args, Command, CommandResult};
+    ///
+    /// fn ip_set(args: &Args, out: &mut dyn Write) -> CommandResult {
+    ///     writeln!(out, "IP is {}", args[0])?;
+    ///     Ok(())
+    /// }
+    ///
+    /// static IP: [Command; 1] = [Command::new("set", "Sets the IP address", ip_set)];
+    /// static NET: [Command; 1] = [Command::group("ip", "IP settings", &IP)];
+    /// static COMMANDS: [Command; 1] = [Command::group("net", "Networking", &NET)];
+    ///
+    /// let mut h = harness::Harness::with_commands(String::new(), &COMMANDS);
+    /// for b in b"net ip set 10.0.0.2\n".iter() {
+    ///     h.receive(*b);
+    /// }
+    /// assert_eq!(h.writer(), "IP is 10.0.0.2\n");
+    /// ```
+    pub const fn group(name: &'a str,
+                       help_text: &'a str,
+                       children: &'a [Command<'a, T>])
+                       -> Command<'a, T> {
+        let mut cmd = Command::build(name, help_text, Handler::Group);
+        cmd.children = children;
+        cmd
+    }
+
+    /// Makes a built-in command available as `name`, e.g.
+    /// `Command::builtin("?", Builtin::Help)`.
+    pub const fn builtin(name: &'a str, builtin: Builtin) -> Command<'a, T> {
+        let mut cmd = Command::build(name, builtin.help_text(), Handler::Builtin(builtin));
+        cmd.params = builtin.params();
+        cmd
+    }
+
+    /// Declares the arguments this command takes. The `Harness` then checks
+    /// them before calling the handler, reporting anything wrong along with
+    /// a usage line, and fills in any defaults. A built-in command always
+    /// takes its own parameters, so these are ignored.
+    ///
+    /// ```
+    /// use std::fmt::Write;
+    /// use harness::{Args, Command, CommandResult, Param};
+    ///
+    /// fn pwm(args: &Args, out: &mut dyn Write) -> CommandResult {
+    ///     let duty = args.int("duty").unwrap();
+    ///     writeln!(out, "Duty is {}%", duty)?;
+    ///     Ok(())
+    /// }
+    ///
+    /// const PWM: [Param; 2] = [Param::int_range("channel", 0, 3), Param::int_range("duty", 0, 100)];
+    /// static COMMANDS: [Command; 1] = [Command::new("pwm", "Sets a PWM output", pwm).with_params(&PWM)];
+    ///
+    /// let mut h = harness::Harness::with_commands(String::new(), &COMMANDS);
+    /// for b in b"pwm 2 150\n".iter() {
+    ///     h.receive_and_print(*b).unwrap();
+    /// }
+    /// assert_eq!(h.writer(),
+    ///            "Error: arg 2 (duty): 150 out of range 0..=100\nUsage: pwm <channel> <duty>\n> ");
+    /// ```
+    pub const fn with_params(mut self, params: &'a [Param<'a>]) -> Command<'a, T> {
+        self.params = params;
+        self
+    }
+
+    /// Like `with_params`, for a command that has already been registered
+    /// with `Harness::add_command`.
+    pub fn params(&mut self, params: &'a [Param<'a>]) -> &mut Command<'a, T> {
+        self.params = params;
+        self
+    }
+
+    /// Gives a longer description of the command, shown by `help <command>`
+    /// after the one-line help text.
+    pub const fn with_description(mut self, description: &'a str) -> Command<'a, T> {
+        self.description = description;
+        self
+    }
+
+    /// Like `with_description`, for a command that has already been
+    /// registered with `Harness::add_command`.
+    pub fn description(&mut self, description: &'a str) -> &mut Command<'a, T> {
+        self.description = description;
+        self
+    }
+
+    /// Gives some example command lines, shown by `help <command>`.
+    pub const fn with_examples(mut self, examples: &'a [&'a str]) -> Command<'a, T> {
+        self.examples = examples;
+        self
+    }
+
+    /// Like `with_examples`, for a command that has already been registered
+    /// with `Harness::add_command`.
+    pub fn examples(&mut self, examples: &'a [&'a str]) -> &mut Command<'a, T> {
+        self.examples = examples;
+        self
+    }
+
+    /// Puts the command under a heading in the `help` listing. Commands
+    /// with the same category are listed together.
+    pub const fn with_category(mut self, category: &'a str) -> Command<'a, T> {
+        self.category = category;
+        self
+    }
+
+    /// Like `with_category`, for a command that has already been registered
+    /// with `Harness::add_command`.
+    pub fn category(&mut self, category: &'a str) -> &mut Command<'a, T> {
+        self.category = category;
+        self
+    }
+
+    /// Writes the full help for this command, which was invoked as `path`.
+    pub(crate) fn write_help(&self, out: &mut dyn Write, path: &[&str]) -> fmt::Result {
+        out.write_str("Command:")?;
+        for word in path {
+            write!(out, " {}", word)?;
+        }
+        writeln!(out, " - {}", self.help_text)?;
+        if !self.description.is_empty() {
+            writeln!(out, "{}", self.description.trim_end())?;
+        }
+        let params = self.checked_params();
+        if !params.is_empty() {
+            param::write_usage(out, path, params)?;
+            writeln!(out)?;
+            writeln!(out, "Arguments:")?;
+            param::write_params(out, params)?;
+        }
+        if !self.examples.is_empty() {
+            writeln!(out, "Examples:")?;
+            for example in self.examples {
+                writeln!(out, "  {}", example)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the function used to complete this command's arguments.
+    pub const fn with_completion(mut self, complete: CompleteFn) -> Command<'a, T> {
+        self.complete = Some(complete);
+        self
+    }
+
+    /// Like `with_completion`, for a command that has already been
+    /// registered with `Harness::add_command`.
+    pub fn completion(&mut self, complete: CompleteFn) -> &mut Command<'a, T> {
+        self.complete = Some(complete);
+        self
+    }
+
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub fn help_text(&self) -> &'a str {
+        self.help_text
+    }
+
+    /// The parameters the command's arguments are checked against. A
+    /// built-in command relies on its arguments fitting its own, so any
+    /// others it is given are ignored.
+    pub(crate) fn checked_params(&self) -> &'a [Param<'a>] {
+        match self.handler {
+            Handler::Builtin(builtin) => builtin.params(),
+            _ => self.params,
+        }
+    }
+
+    pub fn is_group(&self) -> bool {
+        matches!(self.handler, Handler::Group)
+    }
+}
+
+impl<'a, T> Clone for Command<'a, T> {
+    fn clone(&self) -> Command<'a, T> {
+        *self
+    }
+}
+
+impl<'a, T> Copy for Command<'a, T> {}
+
+/// The commands a `Harness` can run: a static table, plus (with the `std`
+/// feature) any registered at run-time.
+pub(crate) struct Commands<'a, T> {
+    table: &'a [Command<'a, T>],
+    #[cfg(feature = "std")]
+    registered: Vec<Command<'a, T>>,
+    #[cfg(feature = "std")]
+    closures: Vec<BoxedHandler<'a, T>>,
+    /// Other names for commands, as `(alias, command)`.
+    alias_table: &'a [(&'a str, &'a str)],
+    #[cfg(feature = "std")]
+    aliases: Vec<(&'a str, &'a str)>,
+    /// The built-in commands available under their own names. Only the
+    /// first `builtin_count` are.
+    builtins: [Command<'a, T>; Builtin::ALL.len()],
+    builtin_count: usize,
+}
+
+/// How the words typed are matched against the names of commands.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Matching {
+    /// May a name be abbreviated to any prefix that is not also a prefix of
+    /// another name?
+    pub prefix: bool,
+    /// Are upper and lower case ASCII letters the same?
+    pub ignore_case: bool,
+    /// How many edits away from an unknown name a name may be to be
+    /// suggested instead. 0 means don't suggest anything.
+    pub distance: usize,
+}
+
+impl Matching {
+    /// Is `word` the same as `name`?
+    pub fn is(&self, name: &str, word: &str) -> bool {
+        name == word || (self.ignore_case && name.eq_ignore_ascii_case(word))
+    }
+
+    /// Does `name` start with `word`?
+    pub fn starts(&self, name: &str, word: &str) -> bool {
+        match name.as_bytes().get(..word.len()) {
+            Some(start) if self.ignore_case => start.eq_ignore_ascii_case(word.as_bytes()),
+            Some(start) => start == word.as_bytes(),
+            None => false,
+        }
+    }
+}
+
+/// How a word typed matches a list of names.
+pub(crate) enum Match<X> {
+    None,
+    One(X),
+    Many,
+}
+
+/// Finds the item called `word`, preferring one whose name has the same case
+/// if case is ignored. Failing that, if prefix matching is on, finds the only
+/// item whose name starts with `word`.
+pub(crate) fn find_match<X, I, F>(items: I, name: F, word: &str, matching: Matching) -> Match<X>
+    where I: Iterator<Item = X> + Clone,
+          F: Fn(&X) -> &str
+{
+    if let Some(item) = items.clone().find(|item| name(item) == word) {
+        return Match::One(item);
+    }
+    if let Some(item) = items.clone().find(|item| matching.is(name(item), word)) {
+        return Match::One(item);
+    }
+    if !matching.prefix || word.is_empty() {
+        return Match::None;
+    }
+    let mut matches = items.filter(|item| matching.starts(name(item), word));
+    match (matches.next(), matches.next()) {
+        (None, _) => Match::None,
+        (Some(item), None) => Match::One(item),
+        _ => Match::Many,
+    }
+}
+
+/// The error for a `word` that is the start of more than one of `names`,
+/// e.g. `st matches start, status, stop`.
+pub(crate) fn ambiguous<'n, I>(word: &str, names: I, matching: Matching) -> Error
+    where I: Iterator<Item = &'n str> + Clone
+{
+    let mut message = Message::new();
+    let _ = write!(message, "{} matches", word);
+    let mut last = None;
+    let mut sep = " ";
+    let matches = names.filter(|name| matching.starts(name, word));
+    while let Some(next) = matches.clone().filter(|name| Some(*name) > last).min() {
+        let _ = write!(message, "{}{}", sep, next);
+        sep = ", ";
+        last = Some(next);
+    }
+    Error::Ambiguous(message)
+}
+
+impl<'a, T> Commands<'a, T> {
+    pub fn new(table: &'a [Command<'a, T>]) -> Commands<'a, T> {
+        Commands {
+            table,
+            #[cfg(feature = "std")]
+            registered: Vec::new(),
+            #[cfg(feature = "std")]
+            closures: Vec::new(),
+            alias_table: &[],
+            #[cfg(feature = "std")]
+            aliases: Vec::new(),
+            builtins: Builtin::ALL.map(|builtin| Command::builtin(builtin.name(), builtin)),
+            builtin_count: 0,
+        }
+    }
+
+    /// Makes exactly these built-in commands available, under their own
+    /// names.
+    pub fn set_builtins(&mut self, builtins: &[Builtin]) {
+        self.builtin_count = 0;
+        for builtin in builtins.iter().take(self.builtins.len()) {
+            self.builtins[self.builtin_count] = Command::builtin(builtin.name(), *builtin);
+            self.builtin_count += 1;
+        }
+    }
+
+    pub fn set_aliases(&mut self, table: &'a [(&'a str, &'a str)]) {
+        self.alias_table = table;
+    }
+
+    /// Every alias, as `(alias, command)`. Those added at run-time come
+    /// first, and hide any in the table with the same name.
+    pub fn aliases(&self) -> impl Iterator<Item = &(&'a str, &'a str)> + Clone {
+        #[cfg(feature = "std")]
+        let added = &self.aliases[..];
+        #[cfg(not(feature = "std"))]
+        let added: &[(&'a str, &'a str)] = &[];
+        added
+            .iter()
+            .chain(self.alias_table.iter().filter(move |(name, _)| !added.iter().any(|(a, _)| a == name)))
+    }
+
+    /// Adds an alias, replacing any existing alias with the same name.
+    #[cfg(feature = "std")]
+    pub fn add_alias(&mut self, alias: &'a str, command: &'a str) {
+        match self.aliases.iter_mut().find(|(name, _)| *name == alias) {
+            Some(existing) => existing.1 = command,
+            None => self.aliases.push((alias, command)),
+        }
+    }
+
+    /// Every top-level command.
+    pub fn iter(&self) -> impl Iterator<Item = &Command<'a, T>> + Clone {
+        self.children(&[], self.table)
+    }
+
+    /// The commands in the group at `path` (the top level, if `path` is
+    /// empty), whose children in the table are `table`. The table comes
+    /// first, then the commands registered at run-time, then (at the top
+    /// level) the built-in commands. A command registered at run-time takes
+    /// the place of any command in the table with the same name, and either
+    /// hides a built-in command with the same name.
+    pub fn children<'s>(&'s self,
+                        path: &'s [&'s str],
+                        table: &'a [Command<'a, T>])
+                        -> impl Iterator<Item = &'s Command<'a, T>> + Clone + 's {
+        #[cfg(feature = "std")]
+        let registered = &self.registered[..];
+        #[cfg(not(feature = "std"))]
+        let registered: &[Command<'a, T>] = &[];
+        #[cfg(feature = "std")]
+        let in_group = move |cmd: &&Command<'a, T>| cmd.parent.split_whitespace().eq(path.iter().copied());
+        #[cfg(not(feature = "std"))]
+        let in_group = move |_: &&Command<'a, T>| path.is_empty();
+        let registered = registered.iter().filter(in_group);
+        let replaced = registered.clone();
+        let hiding = registered.clone();
+        let builtins = if path.is_empty() { &self.builtins[..self.builtin_count] } else { &[] };
+        table
+            .iter()
+            .map(move |cmd| replaced.clone().find(|r| r.name == cmd.name).unwrap_or(cmd))
+            .chain(registered.filter(move |r| !table.iter().any(|cmd| cmd.name == r.name)))
+            .chain(builtins.iter().filter(move |b| {
+                !table.iter().any(|cmd| cmd.name == b.name) && !hiding.clone().any(|r| r.name == b.name)
+            }))
+    }
+
+    /// Finds the command a command line refers to, by walking down through
+    /// any groups named at its start. Returns the command, and how many words
+    /// of `argv` name it. If `argv` ends with the name of a group, that group
+    /// is returned. The full names of the groups and the command are written
+    /// to `path`, which matters if they have been abbreviated or typed in
+    /// another case. If a word isn't a command, the error suggests any
+    /// similar names.
+    pub fn resolve(&self,
+                   argv: &[&str],
+                   matching: Matching,
+                   path: &mut [&'a str; MAX_ARGS])
+                   -> Result<(Command<'a, T>, usize), Error> {
+        let mut cmd: Option<Command<'a, T>> = None;
+        let mut depth = 0;
+        while depth < argv.len() && cmd.is_none_or(|cmd| cmd.is_group()) {
+            let children = match cmd {
+                Some(group) => group.children,
+                None => self.table,
+            };
+            let word = argv[depth];
+            let found = {
+                let candidates = self.children(&path[..depth], children);
+                match find_match(candidates.clone(), |child| child.name, word, matching) {
+                    Match::One(child) => *child,
+                    Match::None => {
+                        let name = word.into();
+                        let names = candidates.map(|child| child.name);
+                        let suggestions = suggest(word, names, matching.distance, matching.ignore_case);
+                        return Err(if depth == 0 {
+                            Error::UnknownCommand { name, suggestions }
+                        } else {
+                            Error::UnknownSubcommand { name, suggestions }
+                        });
+                    }
+                    Match::Many => return Err(ambiguous(word, candidates.map(|child| child.name), matching)),
+                }
+            };
+            cmd = Some(found);
+            path[depth] = found.name;
+            depth += 1;
+        }
+        cmd.map(|cmd| (cmd, depth)).ok_or_else(|| Error::UnknownCommand {
+                                                     name: Message::new(),
+                                                     suggestions: Suggestions::new(),
+                                                 })
+    }
+
+    /// Offers the possible values of the next word on a line that starts
+    /// with `argv`: command names if `argv` is empty or names a group,
+    /// otherwise whatever the command's completion function suggests, or
+    /// failing that the values its parameter allows.
+    pub fn complete(&self, argv: &[&str], matching: Matching, add: &mut dyn FnMut(&str)) {
+        if argv.is_empty() {
+            self.iter().for_each(|cmd| add(cmd.name));
+            return;
+        }
+        let mut path = [""; MAX_ARGS];
+        let matching = Matching { distance: 0, ..matching };
+        match self.resolve(argv, matching, &mut path) {
+            Ok((cmd, depth)) if cmd.is_group() => {
+                self.children(&path[..depth], cmd.children).for_each(|child| add(child.name));
+            }
+            Ok((cmd, depth)) if matches!(cmd.handler, Handler::Builtin(Builtin::Help)) => {
+                self.complete(&argv[depth..], matching, add);
+            }
+            Ok((cmd, depth)) => {
+                if let Some(complete) = cmd.complete {
+                    complete(&Args::new(cmd.name, &argv[depth..]), add);
+                } else if let Some(param) = cmd.checked_params().get(argv.len() - depth) {
+                    param.complete(add);
+                }
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Runs a command's handler.
+    pub fn call(&mut self,
+                handler: Handler<T>,
+                context: &mut T,
+                args: &Args,
+                out: &mut dyn Write)
+                -> Result<Outcome, Error> {
+        match handler {
+            Handler::Fn(f) => f(args, out).map(|()| Outcome::Continue),
+            Handler::Context(f) => f(context, args, out).map(|()| Outcome::Continue),
+            Handler::Outcome(f) => f(context, args, out),
+            #[cfg(feature = "std")]
+            Handler::Boxed(idx) => (self.closures[idx])(context, args, out),
+            // The `Harness` lists the subcommands instead
+            Handler::Group => Ok(Outcome::Continue),
+            // The `Harness` runs these itself
+            Handler::Builtin(_) => Ok(Outcome::Continue),
+        }
+    }
+
+    /// Adds a command with a closure as its handler, replacing any existing
+    /// command of the same name. The name may start with the names of the
+    /// groups the command belongs to, e.g. `"net ip set"`.
+    #[cfg(feature = "std")]
+    pub fn add(&mut self,
+               name: &'a str,
+               help_text: &'a str,
+               handler: BoxedHandler<'a, T>)
+               -> &mut Command<'a, T> {
+        let cmd = Command::build(name, help_text, Handler::Boxed(0));
+        let idx = match self.position(&cmd).map(|i| self.registered[i].handler) {
+            Some(Handler::Boxed(idx)) => {
+                self.closures[idx] = handler;
+                idx
+            }
+            _ => {
+                self.closures.push(handler);
+                self.closures.len() - 1
+            }
+        };
+        let cmd = self.insert(cmd);
+        cmd.handler = Handler::Boxed(idx);
+        cmd
+    }
+
+    /// Adds an empty group, replacing any existing command of the same name.
+    #[cfg(feature = "std")]
+    pub fn add_group(&mut self, name: &'a str, help_text: &'a str) -> &mut Command<'a, T> {
+        self.insert(Command::build(name, help_text, Handler::Group))
+    }
+
+    /// Adds a built-in command under another name, replacing any existing
+    /// command of that name.
+    #[cfg(feature = "std")]
+    pub fn add_builtin(&mut self, name: &'a str, builtin: Builtin) -> &mut Command<'a, T> {
+        self.insert(Command::builtin(name, builtin))
+    }
+
+    /// Where a registered command with the same name and parent as `cmd` is.
+    #[cfg(feature = "std")]
+    fn position(&self, cmd: &Command<'a, T>) -> Option<usize> {
+        let (parent, name) = split_path(cmd.name);
+        self.registered.iter().position(|r| {
+            r.name == name && r.parent.split_whitespace().eq(parent.split_whitespace())
+        })
+    }
+
+    /// Registers a command, splitting any group names off the front of its
+    /// name.
+    #[cfg(feature = "std")]
+    fn insert(&mut self, mut cmd: Command<'a, T>) -> &mut Command<'a, T> {
+        let existing = self.position(&cmd);
+        (cmd.parent, cmd.name) = split_path(cmd.name);
+        let i = match existing {
+            Some(i) => {
+                self.registered[i] = cmd;
+                i
+            }
+            None => {
+                self.registered.push(cmd);
+                self.registered.len() - 1
+            }
+        };
+        &mut self.registered[i]
+    }
+}
+
+/// Splits `"net ip set"` into `("net ip", "set")`.
+#[cfg(feature = "std")]
+fn split_path(name: &str) -> (&str, &str) {
+    name.trim().rsplit_once(' ').unwrap_or(("", name.trim()))
+}
@@ -0,0 +1,123 @@
+/// Gathers the possible completions of a partly typed word, keeping track of
+/// how many there are and the longest prefix they all share.
+pub struct Completions<'p, const N: usize> {
+    partial: &'p str,
+    /// Are upper and lower case ASCII letters the same?
+    ignore_case: bool,
+    count: usize,
+    common: [u8; N],
+    common_len: usize,
+}
+
+impl<'p, const N: usize> Completions<'p, N> {
+    pub fn new(partial: &'p str, ignore_case: bool) -> Completions<'p, N> {
+        Completions {
+            partial,
+            ignore_case,
+            count: 0,
+            common: [0u8; N],
+            common_len: 0,
+        }
+    }
+
+    /// Considers a possible completion. It is ignored if it doesn't start
+    /// with the partial word.
+    pub fn add(&mut self, candidate: &str) {
+        let same = |a: &u8, b: &u8| a == b || (self.ignore_case && a.eq_ignore_ascii_case(b));
+        match candidate.as_bytes().get(..self.partial.len()) {
+            Some(start) if start.iter().zip(self.partial.as_bytes()).all(|(a, b)| same(a, b)) => {}
+            _ => return,
+        }
+        if self.count == 0 {
+            let mut len = candidate.len().min(N);
+            while !candidate.is_char_boundary(len) {
+                len -= 1;
+            }
+            self.common[..len].copy_from_slice(&candidate.as_bytes()[..len]);
+            self.common_len = len;
+        } else {
+            let mut len = self.common[..self.common_len]
+                .iter()
+                .zip(candidate.bytes())
+                .take_while(|(a, b)| same(a, b))
+                .count();
+            while !candidate.is_char_boundary(len) {
+                len -= 1;
+            }
+            self.common_len = len;
+        }
+        self.count += 1;
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// What can be added to the partial word, because every completion
+    /// starts with it.
+    pub fn suffix(&self) -> &str {
+        let start = self.partial.len().min(self.common_len);
+        core::str::from_utf8(&self.common[start..self.common_len]).unwrap_or_default()
+    }
+}
+
+/// Where the word being typed at the end of `line` starts.
+pub fn word_start(line: &str) -> usize {
+    line.rfind([' ', '\t']).map_or(0, |i| i + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{word_start, Completions};
+
+    #[test]
+    fn unique() {
+        let mut c: Completions<16> = Completions::new("st", false);
+        c.add("status");
+        c.add("reset");
+        assert_eq!(c.count(), 1);
+        assert_eq!(c.suffix(), "atus");
+    }
+
+    #[test]
+    fn common_prefix() {
+        let mut c: Completions<16> = Completions::new("s", false);
+        c.add("start");
+        c.add("status");
+        c.add("stop");
+        assert_eq!(c.count(), 3);
+        assert_eq!(c.suffix(), "t");
+        let mut c: Completions<16> = Completions::new("caf", false);
+        c.add("caf\u{e9}");
+        c.add("caf\u{e8}");
+        assert_eq!(c.suffix(), "");
+    }
+
+    #[test]
+    fn ignoring_case() {
+        let mut c: Completions<16> = Completions::new("ST", true);
+        c.add("Status");
+        c.add("start");
+        c.add("reset");
+        assert_eq!(c.count(), 2);
+        assert_eq!(c.suffix(), "a");
+        let mut c: Completions<16> = Completions::new("ST", false);
+        c.add("status");
+        assert_eq!(c.count(), 0);
+    }
+
+    #[test]
+    fn none() {
+        let mut c: Completions<16> = Completions::new("x", false);
+        c.add("start");
+        assert_eq!(c.count(), 0);
+        assert_eq!(c.suffix(), "");
+    }
+
+    #[test]
+    fn words() {
+        assert_eq!(word_start("gpio set PA"), 9);
+        assert_eq!(word_start("gpio set "), 9);
+        assert_eq!(word_start("gp"), 0);
+    }
+}
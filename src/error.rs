@@ -0,0 +1,346 @@
+use core::fmt::{self, Write};
+
+use crate::Suggestions;
+
+/// How many bytes a `Message` can hold without the `std` feature. Anything
+/// longer is cut short. This keeps an `Error` small enough to return cheaply.
+#[cfg(not(feature = "std"))]
+const MESSAGE_LEN: usize = 54;
+
+/// The text of an error message.
+///
+/// With the `std` feature this is a `String`. Without it, the text is kept
+/// in a fixed-size buffer, and anything that doesn't fit is cut off.
+///
+/// A `Message` implements `core::fmt::Write`, so it can be built with
+/// `write!`, or converted from a `&str`.
+#[derive(Clone)]
+pub struct Message {
+    #[cfg(feature = "std")]
+    text: String,
+    #[cfg(not(feature = "std"))]
+    buf: [u8; MESSAGE_LEN],
+    #[cfg(not(feature = "std"))]
+    len: u8,
+}
+
+impl Message {
+    pub fn new() -> Message {
+        Message {
+            #[cfg(feature = "std")]
+            text: String::new(),
+            #[cfg(not(feature = "std"))]
+            buf: [0u8; MESSAGE_LEN],
+            #[cfg(not(feature = "std"))]
+            len: 0,
+        }
+    }
+
+    /// Formats `value` into a new message.
+    pub fn format(value: impl fmt::Display) -> Message {
+        let mut message = Message::new();
+        let _ = write!(message, "{}", value);
+        message
+    }
+
+    #[cfg(feature = "std")]
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    #[cfg(not(feature = "std"))]
+    pub fn as_str(&self) -> &str {
+        // Only whole characters are ever copied in
+        core::str::from_utf8(&self.buf[..usize::from(self.len)]).unwrap_or_default()
+    }
+
+    /// How many more bytes fit.
+    #[cfg(feature = "std")]
+    pub(crate) fn remaining(&self) -> usize {
+        usize::MAX
+    }
+
+    /// How many more bytes fit.
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn remaining(&self) -> usize {
+        MESSAGE_LEN - usize::from(self.len)
+    }
+}
+
+impl Default for Message {
+    fn default() -> Message {
+        Message::new()
+    }
+}
+
+impl Write for Message {
+    #[cfg(feature = "std")]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.text.push_str(s);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let start = usize::from(self.len);
+        let mut len = s.len().min(MESSAGE_LEN - start);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.buf[start..start + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len as u8;
+        Ok(())
+    }
+}
+
+impl<'s> From<&'s str> for Message {
+    fn from(s: &'s str) -> Message {
+        Message::format(s)
+    }
+}
+
+impl PartialEq for Message {
+    fn eq(&self, other: &Message) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Message {}
+
+impl fmt::Debug for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Everything that can go wrong when running a command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The first word of the line isn't a command. Any commands with
+    /// similar names are suggested (see `Harness::set_suggestion_distance`).
+    UnknownCommand { name: Message, suggestions: Suggestions },
+    /// A word following the name of a group isn't one of its commands.
+    UnknownSubcommand { name: Message, suggestions: Suggestions },
+    /// With prefix matching on, a word is the start of more than one command
+    /// name. The message is e.g. `st matches start, status, stop`.
+    Ambiguous(Message),
+    /// The line didn't fit in the `Harness`'s buffer.
+    LineTooLong,
+    /// The line isn't valid UTF-8.
+    InvalidUtf8,
+    /// A quote was opened but never closed.
+    UnterminatedQuote,
+    /// The line ended with a backslash, with nothing to escape.
+    TrailingBackslash,
+    /// A `;`, `&&` or `||` has no command before it, or an `&&` or `||` has
+    /// none after it.
+    MissingCommand { before: bool, operator: &'static str },
+    /// The line has more words than `MAX_ARGS`.
+    TooManyArguments,
+    /// Scripts run other scripts (with `source`) too many levels deep.
+    NestedTooDeep,
+    /// A macro runs itself, directly or through other macros. The message is
+    /// the macro's name.
+    RecursiveMacro(Message),
+    /// An argument doesn't fit the command's parameters (see
+    /// `Command::with_params`).
+    InvalidArgument {
+        /// What is wrong, e.g. `arg 2 (duty): 150 out of range 0..=100`.
+        problem: Message,
+        /// How the command should be used, e.g. `Usage: pwm <channel> <duty>`.
+        usage: Message,
+    },
+    /// A command in a script run with the `source` built-in command failed.
+    /// Without the `std` feature there is nowhere to keep the script's name
+    /// and line, so the command's error is returned as it is instead.
+    #[cfg(feature = "std")]
+    Script {
+        /// The name of the script.
+        name: Message,
+        /// The line the command is on, counting from 1.
+        line: usize,
+        error: Box<Error>,
+    },
+    /// The command's handler failed.
+    Failed(Message),
+    /// Output could not be written. If the `Harness` writes to an
+    /// `IoWriter`, its `take_error` says why.
+    Io {
+        /// What was being written, e.g. `printing help`.
+        context: &'static str,
+        source: fmt::Error,
+    },
+}
+
+impl Error {
+    /// A command failed, for the reason given. Handlers can also use
+    /// `.into()` on a `&str`, or return a `fmt::Error` with `?`.
+    pub fn failed(reason: impl fmt::Display) -> Error {
+        Error::Failed(Message::format(reason))
+    }
+
+    /// Output could not be written while doing `context`.
+    pub(crate) fn io(context: &'static str) -> impl Fn(fmt::Error) -> Error {
+        move |source| Error::Io { context, source }
+    }
+}
+
+impl<'s> From<&'s str> for Error {
+    fn from(reason: &'s str) -> Error {
+        Error::failed(reason)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<String> for Error {
+    fn from(reason: String) -> Error {
+        Error::Failed(Message { text: reason })
+    }
+}
+
+impl From<fmt::Error> for Error {
+    fn from(source: fmt::Error) -> Error {
+        Error::Io { context: "writing output", source }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownCommand { name, suggestions } => {
+                write!(f, "unknown command '{}'", name)?;
+                write_suggestions(f, suggestions)
+            }
+            Error::UnknownSubcommand { name, suggestions } => {
+                write!(f, "unknown subcommand '{}'", name)?;
+                write_suggestions(f, suggestions)
+            }
+            Error::Ambiguous(message) => write!(f, "ambiguous command: {}", message),
+            Error::LineTooLong => f.write_str("command line too long"),
+            Error::InvalidUtf8 => f.write_str("command is invalid UTF-8"),
+            Error::UnterminatedQuote => f.write_str("unterminated quote"),
+            Error::TrailingBackslash => f.write_str("trailing backslash"),
+            Error::MissingCommand { before: true, operator } => write!(f, "no command before '{}'", operator),
+            Error::MissingCommand { before: false, operator } => write!(f, "no command after '{}'", operator),
+            Error::TooManyArguments => f.write_str("too many arguments"),
+            Error::NestedTooDeep => f.write_str("scripts nested too deeply"),
+            Error::RecursiveMacro(name) => write!(f, "macro '{}' runs itself", name),
+            Error::InvalidArgument { problem, usage } => write!(f, "{}\n{}", problem, usage),
+            #[cfg(feature = "std")]
+            Error::Script { name, line, error } => write!(f, "{} line {}: {}", name, line, error),
+            Error::Failed(reason) => write!(f, "{}", reason),
+            Error::Io { context, .. } => write!(f, "I/O error {}", context),
+        }
+    }
+}
+
+/// Writes e.g. `, did you mean 'status'?`, if there are any suggestions.
+fn write_suggestions(f: &mut fmt::Formatter, suggestions: &Suggestions) -> fmt::Result {
+    if suggestions.is_empty() {
+        Ok(())
+    } else {
+        write!(f, ", did you mean {}?", suggestions)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Script { error, .. } => Some(&**error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{Error, Message};
+    use core::fmt::{self, Write};
+
+    #[test]
+    fn message() {
+        let mut message = Message::from("arg ");
+        write!(message, "{}", 2).unwrap();
+        assert_eq!(message.as_str(), "arg 2");
+        assert_eq!(message, Message::format("arg 2"));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Error::UnknownCommand {
+                           name: "frob".into(),
+                           suggestions: Default::default(),
+                       }
+                       .to_string(),
+                   "unknown command 'frob'");
+        assert_eq!(Error::UnknownSubcommand {
+                           name: "stauts".into(),
+                           suggestions: ["status"].iter().copied().collect(),
+                       }
+                       .to_string(),
+                   "unknown subcommand 'stauts', did you mean 'status'?");
+        assert_eq!(Error::from("boom").to_string(), "boom");
+        assert_eq!(Error::failed(format_args!("{} is too big", 150)), Error::Failed("150 is too big".into()));
+        assert_eq!(Error::InvalidArgument {
+                           problem: "arg 1 (n): missing".into(),
+                           usage: "Usage: pwm <n>".into(),
+                       }
+                       .to_string(),
+                   "arg 1 (n): missing\nUsage: pwm <n>");
+    }
+
+    #[test]
+    fn source() {
+        use std::error::Error as _;
+        let e = Error::from(fmt::Error);
+        assert_eq!(e.to_string(), "I/O error writing output");
+        assert!(e.source().unwrap().is::<fmt::Error>());
+        assert!(Error::LineTooLong.source().is_none());
+        let e = Error::Script { name: "boot".into(), line: 2, error: Box::new("boom".into()) };
+        assert_eq!(e.to_string(), "boot line 2: boom");
+        assert_eq!(e.source().unwrap().to_string(), "boom");
+    }
+}
+
+#[cfg(all(test, not(feature = "std")))]
+mod no_std_tests {
+    use super::{Error, Message, MESSAGE_LEN};
+    use core::fmt::Write;
+
+    #[test]
+    fn truncated() {
+        let mut message = Message::new();
+        for _ in 0..10 {
+            write!(message, "0123456789").unwrap();
+        }
+        assert_eq!(message.as_str().len(), MESSAGE_LEN);
+        assert_eq!(message.remaining(), 0);
+        assert!(message.as_str().starts_with("0123456789012"));
+    }
+
+    #[test]
+    fn whole_characters() {
+        // Each `é` is two bytes, so only whole ones fit
+        let mut message = Message::new();
+        for _ in 0..MESSAGE_LEN {
+            message.write_str("\u{e9}").unwrap();
+        }
+        assert_eq!(message.as_str().len(), MESSAGE_LEN / 2 * 2);
+        assert!(message.as_str().chars().all(|c| c == '\u{e9}'));
+    }
+
+    #[test]
+    fn small() {
+        assert!(core::mem::size_of::<Error>() < 128);
+        assert_eq!(Error::from("boom"), Error::Failed(Message::from("boom")));
+    }
+}
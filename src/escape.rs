@@ -0,0 +1,167 @@
+/// A key that sends an escape sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Delete,
+}
+
+/// What a byte received turned out to be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    /// An ordinary byte, not part of an escape sequence.
+    Byte(u8),
+    /// The end of an escape sequence for a key we understand.
+    Key(Key),
+    /// Part of an escape sequence, or the end of one we don't understand.
+    Pending,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Ground,
+    /// Seen ESC.
+    Escape,
+    /// Seen `ESC [`, and possibly some parameters. We only keep the first
+    /// parameter, and whether we have seen anything after it.
+    Csi { param: u8, more: bool },
+    /// Seen `ESC O`.
+    Ss3,
+}
+
+/// Picks ANSI escape sequences (as sent by the arrow keys, Home, End and so
+/// on) out of the bytes received.
+///
+/// Control Sequence Introducer (CSI) sequences are `ESC [`, followed by any
+/// number of parameter bytes (`0x30` to `0x3F`), any number of intermediate
+/// bytes (`0x20` to `0x2F`) and a final byte (`0x40` to `0x7E`). Some
+/// terminals send `ESC O` and a single final byte instead. Any sequence we
+/// don't recognise is swallowed whole.
+pub struct Decoder {
+    state: State,
+}
+
+const ESC: u8 = 0x1B;
+
+impl Decoder {
+    pub const fn new() -> Decoder {
+        Decoder { state: State::Ground }
+    }
+
+    pub fn feed(&mut self, c: u8) -> Input {
+        if c == ESC {
+            // Always start again, even part way through a sequence
+            self.state = State::Escape;
+            return Input::Pending;
+        }
+        match self.state {
+            State::Ground => Input::Byte(c),
+            State::Escape => {
+                self.state = match c {
+                    b'[' => State::Csi { param: 0, more: false },
+                    b'O' => State::Ss3,
+                    _ => State::Ground,
+                };
+                Input::Pending
+            }
+            State::Csi { param, more } => {
+                match c {
+                    b'0'..=b'9' if !more => {
+                        self.state = State::Csi {
+                            param: param.saturating_mul(10).saturating_add(c - b'0'),
+                            more,
+                        };
+                        Input::Pending
+                    }
+                    0x20..=0x3F => {
+                        self.state = State::Csi { param, more: true };
+                        Input::Pending
+                    }
+                    0x40..=0x7E => {
+                        self.state = State::Ground;
+                        Decoder::csi(param, c).map_or(Input::Pending, Input::Key)
+                    }
+                    _ => {
+                        // Not part of a sequence after all
+                        self.state = State::Ground;
+                        Input::Byte(c)
+                    }
+                }
+            }
+            State::Ss3 => {
+                self.state = State::Ground;
+                Decoder::csi(0, c).map_or(Input::Pending, Input::Key)
+            }
+        }
+    }
+
+    fn csi(param: u8, c: u8) -> Option<Key> {
+        match (c, param) {
+            (b'A', _) => Some(Key::Up),
+            (b'B', _) => Some(Key::Down),
+            (b'C', _) => Some(Key::Right),
+            (b'D', _) => Some(Key::Left),
+            (b'H', _) | (b'~', 1) | (b'~', 7) => Some(Key::Home),
+            (b'F', _) | (b'~', 4) | (b'~', 8) => Some(Key::End),
+            (b'~', 3) => Some(Key::Delete),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{Decoder, Input, Key};
+
+    fn decode(bytes: &[u8]) -> Vec<Input> {
+        let mut d = Decoder::new();
+        bytes.iter().map(|b| d.feed(*b)).filter(|i| *i != Input::Pending).collect()
+    }
+
+    #[test]
+    fn arrows() {
+        assert_eq!(decode(b"a\x1b[A\x1bOB\x1b[C\x1b[DB"),
+                   [Input::Byte(b'a'),
+                    Input::Key(Key::Up),
+                    Input::Key(Key::Down),
+                    Input::Key(Key::Right),
+                    Input::Key(Key::Left),
+                    Input::Byte(b'B')]);
+    }
+
+    #[test]
+    fn editing_keys() {
+        assert_eq!(decode(b"\x1b[H\x1b[1~\x1bOH\x1b[7~\x1b[F\x1b[4~\x1b[8~\x1b[3~"),
+                   [Input::Key(Key::Home),
+                    Input::Key(Key::Home),
+                    Input::Key(Key::Home),
+                    Input::Key(Key::Home),
+                    Input::Key(Key::End),
+                    Input::Key(Key::End),
+                    Input::Key(Key::End),
+                    Input::Key(Key::Delete)]);
+    }
+
+    #[test]
+    fn modifiers() {
+        // Ctrl-Right is still Right
+        assert_eq!(decode(b"\x1b[1;5C"), [Input::Key(Key::Right)]);
+    }
+
+    #[test]
+    fn unknown() {
+        // F5, Page Up, Alt-x and a mouse report are all ignored
+        assert_eq!(decode(b"\x1b[15~\x1b[5~\x1bx\x1b[<0;10;20Mok"),
+                   [Input::Byte(b'o'), Input::Byte(b'k')]);
+    }
+
+    #[test]
+    fn interrupted() {
+        assert_eq!(decode(b"\x1b[1\n\x1b\x1b[A"),
+                   [Input::Byte(b'\n'), Input::Key(Key::Up)]);
+    }
+}
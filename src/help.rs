@@ -0,0 +1,192 @@
+use core::fmt::{self, Write};
+
+use crate::Command;
+
+/// The order `help` lists commands in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HelpOrder {
+    /// The order of the table given to `Harness::with_commands`, followed by
+    /// the commands added at run-time in the order they were added. A
+    /// command added at run-time that replaces one in the table takes its
+    /// place.
+    Registration,
+    /// Sorted by name.
+    Alphabetical,
+}
+
+/// Calls `f` with each command in turn, in the given order.
+fn for_each<'c, 'a: 'c, T: 'a, I, F>(cmds: I, order: HelpOrder, mut f: F) -> fmt::Result
+    where I: Iterator<Item = &'c Command<'a, T>> + Clone,
+          F: FnMut(&Command<'a, T>) -> fmt::Result
+{
+    match order {
+        HelpOrder::Registration => cmds.into_iter().try_for_each(f),
+        HelpOrder::Alphabetical => {
+            let mut last: Option<&str> = None;
+            while let Some(next) = cmds.clone()
+                .filter(|cmd| Some(cmd.name) > last)
+                .min_by_key(|cmd| cmd.name) {
+                f(next)?;
+                last = Some(next.name);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Calls `f` with each category the commands are in, in the given order.
+/// Commands with no category are in the category `""`.
+fn for_each_category<'c, 'a: 'c, T: 'a, I, F>(cmds: I, order: HelpOrder, mut f: F) -> fmt::Result
+    where I: Iterator<Item = &'c Command<'a, T>> + Clone,
+          F: FnMut(&'a str) -> fmt::Result
+{
+    match order {
+        HelpOrder::Registration => {
+            for (i, cmd) in cmds.clone().enumerate() {
+                if !cmds.clone().take(i).any(|earlier| earlier.category == cmd.category) {
+                    f(cmd.category)?;
+                }
+            }
+            Ok(())
+        }
+        HelpOrder::Alphabetical => {
+            let mut last: Option<&str> = None;
+            while let Some(next) = cmds.clone()
+                .map(|cmd| cmd.category)
+                .filter(|category| Some(*category) > last)
+                .min() {
+                f(next)?;
+                last = Some(next);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Writes one line per command, with the names in a column and the help
+/// text wrapped to `width` columns (if `width` isn't 0). Commands with a
+/// category are listed under a heading, and any macros, as `(name, command
+/// line)`, are listed last under the heading `Macros`.
+pub(crate) fn write_listing<'c, 'a: 'c, 'm, T: 'a, I, M>(out: &mut dyn Write,
+                                                          cmds: I,
+                                                          macros: M,
+                                                          order: HelpOrder,
+                                                          width: usize)
+                                                          -> fmt::Result
+    where I: Iterator<Item = &'c Command<'a, T>> + Clone,
+          M: Iterator<Item = (&'m str, &'m str)> + Clone
+{
+    let name_width = cmds.clone()
+        .map(|cmd| cmd.name)
+        .chain(macros.clone().map(|(name, _)| name))
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut first = true;
+    for_each_category(cmds.clone(), order, |category| {
+        if !category.is_empty() {
+            if !first {
+                writeln!(out)?;
+            }
+            writeln!(out, "{}:", category)?;
+        }
+        first = false;
+        let in_category = cmds.clone().filter(|cmd| cmd.category == category);
+        for_each(in_category, order, |cmd| {
+            write!(out, "  {:width$}  ", cmd.name, width = name_width)?;
+            write_wrapped(out, cmd.help_text, name_width + 4, width)
+        })
+    })?;
+    for (i, (name, commands)) in macros.enumerate() {
+        if i == 0 {
+            if !first {
+                writeln!(out)?;
+            }
+            writeln!(out, "Macros:")?;
+        }
+        write!(out, "  {:width$}  ", name, width = name_width)?;
+        write_wrapped(out, commands, name_width + 4, width)?;
+    }
+    Ok(())
+}
+
+/// Writes `text`, which starts at column `indent`, and a newline. If a word
+/// would go past column `width`, it starts a new line indented to `indent`
+/// instead.
+pub(crate) fn write_wrapped(out: &mut dyn Write, text: &str, indent: usize, width: usize) -> fmt::Result {
+    let mut column = indent;
+    for (i, word) in text.split_whitespace().enumerate() {
+        let len = word.chars().count();
+        if i > 0 {
+            if width != 0 && column + 1 + len > width {
+                write!(out, "\n{:indent$}", "", indent = indent)?;
+                column = indent;
+            } else {
+                out.write_char(' ')?;
+                column += 1;
+            }
+        }
+        out.write_str(word)?;
+        column += len;
+    }
+    writeln!(out)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{write_listing, write_wrapped, HelpOrder};
+    use crate::{Args, Command, CommandResult};
+    use core::fmt::Write;
+
+    fn nop(_args: &Args, _out: &mut dyn Write) -> CommandResult {
+        Ok(())
+    }
+
+    static COMMANDS: [Command; 4] = [Command::new("reset", "Resets the board.", nop),
+                                     Command::new("led", "Sets an LED.", nop).with_category("Hardware"),
+                                     Command::new("adc", "Reads the ADC.", nop).with_category("Hardware"),
+                                     Command::new("clear", "Clears the screen.", nop)];
+
+    fn listing(order: HelpOrder, width: usize) -> String {
+        let mut out = String::new();
+        write_listing(&mut out, COMMANDS.iter(), core::iter::empty(), order, width).unwrap();
+        out
+    }
+
+    #[test]
+    fn registration() {
+        assert_eq!(listing(HelpOrder::Registration, 0),
+                   "  reset  Resets the board.\n  clear  Clears the screen.\n\nHardware:\n  led    \
+                    Sets an LED.\n  adc    Reads the ADC.\n");
+    }
+
+    #[test]
+    fn alphabetical() {
+        assert_eq!(listing(HelpOrder::Alphabetical, 0),
+                   "  clear  Clears the screen.\n  reset  Resets the board.\n\nHardware:\n  adc    \
+                    Reads the ADC.\n  led    Sets an LED.\n");
+    }
+
+    #[test]
+    fn macros() {
+        let mut out = String::new();
+        let macros = [("calib", "adc reset; adc cal 3"), ("go", "reset")];
+        let order = HelpOrder::Registration;
+        write_listing(&mut out, COMMANDS[..1].iter(), macros.iter().copied(), order, 0).unwrap();
+        assert_eq!(out,
+                   "  reset  Resets the board.\n\nMacros:\n  calib  adc reset; adc cal 3\n  go     reset\n");
+        out.clear();
+        write_listing(&mut out, COMMANDS[..0].iter(), macros.iter().copied(), order, 0).unwrap();
+        assert_eq!(out, "Macros:\n  calib  adc reset; adc cal 3\n  go     reset\n");
+    }
+
+    #[test]
+    fn wrapped() {
+        let mut out = String::new();
+        write_wrapped(&mut out, "The quick brown fox jumps over the lazy dog", 4, 20).unwrap();
+        assert_eq!(out, "The quick brown\n    fox jumps over\n    the lazy dog\n");
+        out.clear();
+        write_wrapped(&mut out, "Antidisestablishmentarianism is long", 4, 10).unwrap();
+        assert_eq!(out, "Antidisestablishmentarianism\n    is\n    long\n");
+    }
+}
@@ -0,0 +1,77 @@
+/// A ring of the last `D` command lines, each up to `N` bytes long. It needs
+/// no allocator. A depth of zero disables history altogether.
+pub struct History<const N: usize, const D: usize> {
+    entries: [[u8; N]; D],
+    lens: [usize; D],
+    /// Where the next entry will go.
+    next: usize,
+    count: usize,
+}
+
+impl<const N: usize, const D: usize> History<N, D> {
+    pub const fn new() -> History<N, D> {
+        History {
+            entries: [[0u8; N]; D],
+            lens: [0; D],
+            next: 0,
+            count: 0,
+        }
+    }
+
+    /// Adds a line, pushing out the oldest if the history is full. A line the
+    /// same as the most recent one is not added again.
+    pub fn push(&mut self, line: &[u8]) {
+        if D == 0 || line.len() > N || self.get(0) == Some(line) {
+            return;
+        }
+        self.entries[self.next][..line.len()].copy_from_slice(line);
+        self.lens[self.next] = line.len();
+        self.next = (self.next + 1) % D;
+        self.count = (self.count + 1).min(D);
+    }
+
+    /// Gets a line, where 0 is the most recent.
+    pub fn get(&self, age: usize) -> Option<&[u8]> {
+        if age >= self.count {
+            return None;
+        }
+        let idx = (self.next + D - 1 - age) % D;
+        Some(&self.entries[idx][..self.lens[idx]])
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+
+    #[test]
+    fn ring() {
+        let mut h: History<8, 3> = History::new();
+        assert_eq!(h.len(), 0);
+        assert_eq!(h.get(0), None);
+        h.push(b"one");
+        h.push(b"two");
+        h.push(b"two");
+        assert_eq!(h.len(), 2);
+        assert_eq!(h.get(0), Some(&b"two"[..]));
+        assert_eq!(h.get(1), Some(&b"one"[..]));
+        h.push(b"three");
+        h.push(b"four");
+        assert_eq!(h.len(), 3);
+        assert_eq!(h.get(0), Some(&b"four"[..]));
+        assert_eq!(h.get(2), Some(&b"two"[..]));
+        assert_eq!(h.get(3), None);
+    }
+
+    #[test]
+    fn disabled() {
+        let mut h: History<8, 0> = History::new();
+        h.push(b"one");
+        assert_eq!(h.len(), 0);
+        assert_eq!(h.get(0), None);
+    }
+}
@@ -1,161 +1,2576 @@
-use std::io::Write;
-use std::collections::HashMap;
-
-/// Represents a command that can be called. It has a function that is called when its name
-/// is entered at the command line.
-pub struct Command<'a> {
-    help_text: &'a str,
-    handler: fn() -> Result<(), &'static str>,
+//! A simple command line handler.
+//!
+//! With the default `std` feature, commands can be closures registered at
+//! run-time. Without it the crate is `#![no_std]`, needs no allocator, and
+//! commands are given to the `Harness` as a static table.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+extern crate core;
+
+use core::fmt::{self, Write};
+
+mod args;
+mod builtin;
+mod command;
+mod complete;
+mod error;
+mod escape;
+mod help;
+mod history;
+mod line;
+mod output;
+mod param;
+mod script;
+mod suggest;
+mod vars;
+mod watch;
+#[cfg(feature = "std")]
+mod writer;
+
+pub use args::{Args, MAX_ARGS};
+pub use builtin::{Builtin, MAX_REPEAT};
+pub use command::{Command, CommandFn, CommandResult, CompleteFn, ContextFn, Outcome, OutcomeFn};
+pub use error::{Error, Message};
+pub use help::HelpOrder;
+pub use output::LineEnding;
+pub use param::Param;
+pub use script::{OnError, ScriptError, Scripts};
+pub use suggest::Suggestions;
+pub use watch::Clock;
+#[cfg(feature = "std")]
+pub use writer::IoWriter;
+
+use args::Link;
+use command::{Commands, Handler, Match, Matching};
+use complete::Completions;
+use escape::{Decoder, Input, Key};
+use history::History;
+use line::Line;
+use output::Output;
+use vars::{Macros, Scope, Variables};
+use watch::Watch;
+
+/// The longest command line a `Harness` accepts, unless told otherwise.
+pub const DEFAULT_LINE_LENGTH: usize = 128;
+
+/// How many command lines a `Harness` remembers, unless told otherwise.
+pub const DEFAULT_HISTORY_DEPTH: usize = 8;
+
+/// How wide help text is wrapped to, unless told otherwise.
+pub const DEFAULT_HELP_WIDTH: usize = 80;
+
+/// How many edits away from an unknown command name the names suggested
+/// instead may be, unless told otherwise.
+pub const DEFAULT_SUGGESTION_DISTANCE: usize = 2;
+
+const CTRL_A: u8 = 0x01;
+const CTRL_C: u8 = 0x03;
+const CTRL_E: u8 = 0x05;
+const BACKSPACE: u8 = 0x08;
+const CTRL_U: u8 = 0x15;
+const CTRL_W: u8 = 0x17;
+const DELETE: u8 = 0x7F;
+
+/// The built-in commands available unless `Harness::set_builtins` says
+/// otherwise.
+const DEFAULT_BUILTINS: [Builtin; 2] = [Builtin::Help, Builtin::History];
+
+/// The escape sequence that clears the screen and homes the cursor.
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// Every name that can start a command line: the aliases and the top-level
+/// commands (including the built-in ones).
+fn top_level<'s, 'a, T>(commands: &'s Commands<'a, T>) -> impl Iterator<Item = &'a str> + Clone + 's {
+    commands
+        .aliases()
+        .map(|(alias, _)| *alias)
+        .chain(commands.iter().map(|cmd| cmd.name))
+}
+
+/// Offers the possible values of the next word on a line that starts with
+/// `argv`, each once, even if e.g. a macro has the same name as a command.
+fn offer<T>(commands: &Commands<T>,
+            macros: &Macros,
+            argv: &[&str],
+            matching: Matching,
+            add: &mut dyn FnMut(&str)) {
+    let mut index = 0;
+    offer_all(commands, macros, argv, matching, &mut |candidate| {
+        let mut seen = 0;
+        let mut earlier = false;
+        offer_all(commands, macros, argv, matching, &mut |other| {
+            earlier |= seen < index && other == candidate;
+            seen += 1;
+        });
+        if !earlier {
+            add(candidate);
+        }
+        index += 1;
+    });
+}
+
+/// Like `offer`, but may offer a value more than once.
+fn offer_all<T>(commands: &Commands<T>,
+                macros: &Macros,
+                argv: &[&str],
+                matching: Matching,
+                add: &mut dyn FnMut(&str)) {
+    if argv.is_empty() {
+        top_level(commands).for_each(&mut *add);
+        macros.iter().for_each(|(name, _)| add(name));
+    } else {
+        commands.complete(argv, matching, add);
+    }
+}
+
+/// A command line handler.
+///
+/// Handlers may borrow whatever they like for the lifetime `'a`. If that is
+/// awkward (for example, because your main loop also needs the object the
+/// handler mutates), register the command with `add_context_command` (or
+/// `Command::with_context`) instead and pass the object in as the context of
+/// type `T` each time you call `receive_with`.
+///
+/// Command lines longer than `N` bytes are rejected. The last `H` command
+/// lines are kept, and can be recalled with the up and down arrow keys.
+pub struct Harness<'a,
+                   W,
+                   T = (),
+                   const N: usize = DEFAULT_LINE_LENGTH,
+                   const H: usize = DEFAULT_HISTORY_DEPTH> {
+    line: Line<N>,
+    history: History<N, H>,
+    /// Which history entry is on the line, if any. 0 is the most recent.
+    recalled: Option<usize>,
+    decoder: Decoder,
+    echo: bool,
+    /// Which line endings are accepted on input. `None` means any of them.
+    input_ending: Option<LineEnding>,
+    /// Was the last byte received a `\r` that ended a line?
+    after_cr: bool,
+    /// Was the last byte received a Tab?
+    tabbed: bool,
+    help_order: HelpOrder,
+    /// How wide the terminal is, for wrapping help text. 0 means don't wrap.
+    help_width: usize,
+    matching: Matching,
+    /// What the `version` built-in prints.
+    version: &'a str,
+    /// Where the `source` built-in finds scripts.
+    scripts: Option<&'a dyn Scripts>,
+    /// How many scripts run with `source` are running.
+    script_depth: usize,
+    variables: Variables,
+    /// Did the last command fail? This is what `$?` expands to.
+    failed: bool,
+    macros: Macros,
+    /// Which macros are running, by their position in `macros`.
+    expanding: [bool; vars::MAX_MACROS],
+    /// What the `watch` built-in tells the time with.
+    clock: Option<&'a dyn Clock>,
+    /// The command being watched, if any.
+    watch: Option<Watch<N>>,
+    /// May the `watch` built-in start watching a command? Only the one
+    /// command on a line typed at the prompt may, so that nothing else runs
+    /// while it is being watched.
+    may_watch: bool,
+    /// Are errors printed when a later command on the same line runs? Only
+    /// `receive_and_print` prints them, as otherwise they are the caller's
+    /// to report.
+    print_errors: bool,
+    commands: Commands<'a, T>,
+    writer: Output<W>,
+}
+
+impl<'a, W, T> Harness<'a, W, T>
+    where W: Write
+{
+    pub fn new(writer: W) -> Harness<'a, W, T> {
+        Harness::sized(writer, &[])
+    }
+
+    /// Creates a `Harness` which runs the commands in the given table.
+    pub fn with_commands(writer: W, table: &'a [Command<'a, T>]) -> Harness<'a, W, T> {
+        Harness::sized(writer, table)
+    }
+}
+
+impl<'a, W, T, const N: usize, const H: usize> Harness<'a, W, T, N, H>
+    where W: Write
+{
+    /// Like `with_commands`, but for a `Harness` with a line length or
+    /// history depth other than the default, e.g.
+    /// `let h: Harness<_, _, 32, 4> = Harness::sized(writer, &COMMANDS);`
+    pub fn sized(writer: W, table: &'a [Command<'a, T>]) -> Harness<'a, W, T, N, H> {
+        let mut commands = Commands::new(table);
+        commands.set_builtins(&DEFAULT_BUILTINS);
+        Harness {
+            line: Line::new(),
+            history: History::new(),
+            recalled: None,
+            decoder: Decoder::new(),
+            echo: false,
+            input_ending: None,
+            after_cr: false,
+            tabbed: false,
+            help_order: HelpOrder::Registration,
+            help_width: DEFAULT_HELP_WIDTH,
+            matching: Matching {
+                prefix: false,
+                ignore_case: false,
+                distance: DEFAULT_SUGGESTION_DISTANCE,
+            },
+            version: "",
+            scripts: None,
+            script_depth: 0,
+            variables: Variables::new("variable"),
+            failed: false,
+            macros: Macros::new("macro"),
+            expanding: [false; vars::MAX_MACROS],
+            clock: None,
+            watch: None,
+            may_watch: false,
+            print_errors: false,
+            commands,
+            writer: Output::new(writer),
+        }
+    }
+
+    /// Gives access to the writer that all output is sent to.
+    pub fn writer(&self) -> &W {
+        &self.writer.inner
+    }
+
+    /// Gives mutable access to the writer that all output is sent to.
+    pub fn writer_mut(&mut self) -> &mut W {
+        &mut self.writer.inner
+    }
+
+    /// Chooses which built-in commands are available, under their own names.
+    /// By default only `help` and `history` are. To have a built-in command
+    /// under another name, put a `Command::builtin` in the table given to
+    /// `with_commands` (or use `add_builtin`).
+    ///
+    /// ```
+    /// use harness::{Builtin, Harness};
+    ///
+    /// let mut h = Harness::new(String::new());
+    /// h.set_builtins(&[Builtin::Help, Builtin::Echo]);
+    /// for b in b"echo hello  world\n".iter() {
+    ///     h.receive(*b);
+    /// }
+    /// assert_eq!(h.writer(), "hello world\n");
+    /// ```
+    pub fn set_builtins(&mut self, builtins: &[Builtin]) {
+        self.commands.set_builtins(builtins);
+    }
+
+    /// Sets what the `version` built-in command prints.
+    pub fn set_version(&mut self, version: &'a str) {
+        self.version = version;
+    }
+
+    /// Lists the top-level commands, with their help text.
+    pub fn print_help(&mut self) -> fmt::Result {
+        help::write_listing(&mut self.writer,
+                            self.commands.iter(),
+                            self.macros.iter(),
+                            self.help_order,
+                            self.help_width)
+    }
+
+    /// Sets the order `help` lists commands in. The default is
+    /// `HelpOrder::Registration`.
+    pub fn set_help_order(&mut self, order: HelpOrder) {
+        self.help_order = order;
+    }
+
+    /// Sets how wide the terminal is, so that `help` can wrap long lines of
+    /// help text to fit. 0 turns wrapping off. The default is
+    /// `DEFAULT_HELP_WIDTH`.
+    pub fn set_help_width(&mut self, width: usize) {
+        self.help_width = width;
+    }
+
+    /// Prints help for the command or group named by `path`, e.g.
+    /// `["net", "ip"]`. For a group, that means listing its children. For a
+    /// command, it means the help text, any description, a usage line and a
+    /// description of each argument (if it has parameters), and any
+    /// examples.
+    pub fn print_help_for(&mut self, path: &[&str]) -> CommandResult {
+        if path.is_empty() {
+            return self.print_help().map_err(Error::io("printing help"));
+        }
+        if let Some(idx) = self.find_macro(path)? {
+            let (name, commands) = self.macros.at(idx);
+            return writeln!(self.writer, "Macro: {} = {}", name, commands)
+                .map_err(Error::io("printing help"));
+        }
+        let mut words = [""; MAX_ARGS];
+        let count = self.expand(path, &mut words)?;
+        let mut path = [""; MAX_ARGS];
+        let (cmd, depth) = self.resolve(&words[..count], &mut path)?;
+        let path = &path[..depth];
+        let result = if cmd.is_group() {
+            let children = self.commands.children(path, cmd.children);
+            help::write_listing(&mut self.writer,
+                                children,
+                                core::iter::empty(),
+                                self.help_order,
+                                self.help_width)
+        } else {
+            cmd.write_help(&mut self.writer, path)
+        };
+        result.map_err(Error::io("printing help"))
+    }
+
+    /// Turns on (or off) matching of abbreviated command names. With it on,
+    /// any prefix of a command name that is not also a prefix of another
+    /// command name at the same level runs that command, so `st` could run
+    /// `status`. It is off by default.
+    pub fn set_prefix_matching(&mut self, prefix_matching: bool) {
+        self.matching.prefix = prefix_matching;
+    }
+
+    /// Turns on (or off) ignoring the case of ASCII letters in command
+    /// names and in the values of `Param::choice` arguments, so that
+    /// `STATUS` runs `status`. Help still shows names as they were given,
+    /// and handlers see the value as it is spelt in the parameter's
+    /// choices. It is off by default.
+    pub fn set_ignore_case(&mut self, ignore_case: bool) {
+        self.matching.ignore_case = ignore_case;
+    }
+
+    /// Sets how many edits (inserting, removing or changing a character, or
+    /// swapping two) a command name may be from an unknown one to be
+    /// suggested in its place, as in
+    /// `unknown command 'stauts', did you mean 'status'?`. 0 turns
+    /// suggestions off. The default is `DEFAULT_SUGGESTION_DISTANCE`.
+    pub fn set_suggestion_distance(&mut self, distance: usize) {
+        self.matching.distance = distance;
+    }
+
+    /// Gives a table of aliases, as `(alias, command)`. Typing an alias is
+    /// the same as typing the command (which may include the names of
+    /// groups, or be a built-in such as `help`), so given
+    /// `[("?", "help"), ("ip", "net ip")]`, `ip show` runs `net ip show`.
+    pub fn set_aliases(&mut self, aliases: &'a [(&'a str, &'a str)]) {
+        self.commands.set_aliases(aliases);
+    }
+
+    /// Replaces the first word of `argv` with the full name of the built-in
+    /// command, alias or command it refers to, then expands any alias. The
+    /// result goes in `words`, and the number of words is returned.
+    fn expand<'w>(&self, argv: &[&'w str], words: &mut [&'w str; MAX_ARGS]) -> Result<usize, Error>
+        where 'a: 'w
+    {
+        let first = match argv.first() {
+            Some(first) => *first,
+            None => return Ok(0),
+        };
+        let name = match command::find_match(top_level(&self.commands), |name| name, first, self.matching) {
+            Match::One(name) => name,
+            Match::None => {
+                return Err(Error::UnknownCommand {
+                    name: first.into(),
+                    suggestions: suggest::suggest(first,
+                                                  self.names(),
+                                                  self.matching.distance,
+                                                  self.matching.ignore_case),
+                })
+            }
+            Match::Many => return Err(command::ambiguous(first, self.names(), self.matching)),
+        };
+        let expansion = self.commands
+            .aliases()
+            .find(|(alias, _)| *alias == name)
+            .map_or(name, |(_, command)| *command);
+        let mut count = 0;
+        for word in expansion.split_whitespace().chain(argv[1..].iter().copied()) {
+            if count == MAX_ARGS {
+                return Err(Error::TooManyArguments);
+            }
+            words[count] = word;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Every name that can start a command line: those from `top_level`,
+    /// and the macros.
+    fn names(&self) -> impl Iterator<Item = &str> + Clone {
+        top_level(&self.commands).map(|name| name as &str).chain(self.macros.iter().map(|(name, _)| name))
+    }
+
+    /// Finds the macro that the first word of `argv` names, if it names one.
+    /// A macro hides any command with the same name.
+    fn find_macro(&self, argv: &[&str]) -> Result<Option<usize>, Error> {
+        let first = match argv.first() {
+            Some(first) if !self.macros.is_empty() => *first,
+            _ => return Ok(None),
+        };
+        match command::find_match(self.names(), |name| name, first, self.matching) {
+            Match::One(name) => Ok(self.macros.position(name)),
+            Match::None => Ok(None),
+            Match::Many => Err(command::ambiguous(first, self.names(), self.matching)),
+        }
+    }
+
+    /// Whether `argv` is `alias <name> =`, the start of a macro definition.
+    fn defines_macro(&self, argv: &[&str]) -> bool {
+        let mut words = [""; MAX_ARGS];
+        let mut path = [""; MAX_ARGS];
+        let count = match (self.find_macro(argv), self.expand(argv, &mut words)) {
+            (Ok(None), Ok(count)) => count,
+            _ => return false,
+        };
+        match self.resolve(&words[..count], &mut path) {
+            Ok((cmd, depth)) => {
+                matches!(cmd.handler, Handler::Builtin(Builtin::Alias))
+                    && matches!(words[depth..count], [_, "="])
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Finds the command `words` refers to (see `Commands::resolve`).
+    fn resolve(&self, words: &[&str], path: &mut [&'a str; MAX_ARGS]) -> Result<(Command<'a, T>, usize), Error> {
+        self.commands.resolve(words, self.matching, path)
+    }
+
+    /// Prints the command lines in the history, oldest first.
+    pub fn print_history(&mut self) -> fmt::Result {
+        let len = self.history.len();
+        for age in (0..len).rev() {
+            let line = self.history.get(age).unwrap_or_default();
+            writeln!(self.writer,
+                     "{:5}  {}",
+                     len - age,
+                     core::str::from_utf8(line).unwrap_or_default())?;
+        }
+        Ok(())
+    }
+
+    /// Turns local echo on or off. With echo on, every character typed is
+    /// written back to the writer, which is what you need when the terminal
+    /// at the other end (e.g. minicom or picocom on a raw UART) does not echo
+    /// what the user types. Echo is off by default.
+    pub fn set_echo(&mut self, echo: bool) {
+        self.echo = echo;
+    }
+
+    /// Sets which line endings end a command line. By default `\r`, `\n`
+    /// and `\r\n` are all accepted (and `\r\n` only runs the command once).
+    /// If a particular ending is given, the other line ending characters are
+    /// ignored. `LineEnding::CrLf` lines end on the `\n`.
+    pub fn set_input_line_ending(&mut self, ending: Option<LineEnding>) {
+        self.input_ending = ending;
+    }
+
+    /// Sets the line ending written at the end of each line of output. This
+    /// applies to everything sent to the writer, including the output of
+    /// command handlers. The default is `LineEnding::Lf`.
+    pub fn set_output_line_ending(&mut self, ending: LineEnding) {
+        self.writer.ending = ending;
+    }
+
+    pub fn prompt(&mut self) -> fmt::Result {
+        write!(self.writer, "> ")
+    }
+
+    /// Like `receive_with`, but prints any error, and then the prompt for
+    /// the next command. Returns what should happen next: if the session
+    /// is ending or restarting, no prompt is printed. Nor is it while a
+    /// command is being watched, until a key stops the watch.
+    pub fn receive_and_print_with(&mut self, context: &mut T, c: u8) -> Result<Outcome, fmt::Error> {
+        self.print_errors = true;
+        let received = self.receive_with(context, c);
+        self.print_errors = false;
+        match received {
+            None => return Ok(Outcome::Continue),
+            Some(Ok(Outcome::Continue)) => {}
+            Some(Ok(outcome)) => return Ok(outcome),
+            Some(Err(s)) => writeln!(self.writer, "Error: {}", s)?,
+        }
+        if self.watch.is_none() {
+            self.prompt()?;
+        }
+        Ok(Outcome::Continue)
+    }
+
+    /// Handles a byte received from the user. Returns the result of running
+    /// a command if the byte completed a line: either an error, or what
+    /// should happen next (see `Command::with_outcome`).
+    ///
+    /// Some control characters edit the line: backspace or delete erases
+    /// the character before the cursor, Ctrl-W erases the word before the
+    /// cursor, and Ctrl-U erases
+    /// everything before the cursor. Ctrl-C abandons the line, as if it were
+    /// empty. The up and down arrow keys recall lines from the history.
+    ///
+    /// The cursor can be moved with the left and right arrow keys, Home and
+    /// End (or Ctrl-A and Ctrl-E), and Delete erases the character under the
+    /// cursor. Other escape sequences are ignored, as are other control
+    /// characters.
+    ///
+    /// Tab completes the word before the cursor, if there is only one way to
+    /// do so; pressing it again lists the possibilities. The first word is
+    /// completed from the command names, and later words by the command's
+    /// completion function, if it has one (see `Command::with_completion`).
+    ///
+    /// See `set_input_line_ending` for which characters end a line.
+    pub fn receive_with(&mut self, context: &mut T, c: u8) -> Option<Result<Outcome, Error>> {
+        if self.watch.is_some() {
+            // Any key stops the watch, once all of it has arrived
+            if let Input::Pending = self.decoder.feed(c) {
+                return None;
+            }
+            self.watch = None;
+            self.after_cr = c == b'\r';
+            return Some(Ok(Outcome::Continue));
+        }
+        let tabbed = core::mem::replace(&mut self.tabbed, false);
+        let c = match self.decoder.feed(c) {
+            Input::Byte(c) => c,
+            Input::Key(key) => {
+                self.key(key);
+                return None;
+            }
+            Input::Pending => return None,
+        };
+        let after_cr = core::mem::replace(&mut self.after_cr, false);
+        match c {
+            b'\r' | b'\n' => {
+                if (c == b'\n' && after_cr) || !self.ends_line(c) {
+                    // Either the second half of a CRLF, or a line ending we
+                    // have been told to ignore.
+                    return None;
+                }
+                self.after_cr = c == b'\r';
+                if self.echo {
+                    let _ = writeln!(self.writer);
+                }
+                return Some(self.process_with(context));
+            }
+            BACKSPACE | DELETE => {
+                if self.line.backspace() {
+                    self.redraw(1, 1);
+                }
+            }
+            CTRL_W => {
+                let count = self.line.erase_word();
+                self.redraw(count, count);
+            }
+            CTRL_U => {
+                let count = self.line.erase_to_start();
+                self.redraw(count, count);
+            }
+            CTRL_A => self.key(Key::Home),
+            CTRL_E => self.key(Key::End),
+            CTRL_C => {
+                self.line.clear();
+                self.recalled = None;
+                let _ = writeln!(self.writer, "^C");
+                return Some(Ok(Outcome::Continue));
+            }
+            b'\t' => self.tabbed = !self.complete(tabbed),
+            _ if c.is_ascii_control() => {
+                // Ignore any other control characters
+            }
+            _ => self.insert(c),
+        }
+        None
+    }
+
+    /// Inserts a byte at the cursor, echoing it if required and redrawing the
+    /// rest of the line.
+    fn insert(&mut self, c: u8) {
+        let at_end = self.line.after().is_empty();
+        if !self.line.insert(c) {
+            // Dropped, but echo it anyway so that rubbing it out again looks
+            // right.
+            if self.echo && at_end && c.is_ascii_graphic() {
+                let _ = self.writer.write_char(c as char);
+            }
+            return;
+        }
+        // Multi-byte characters are written once they are complete.
+        if let Some(s) = self.line.last_char() {
+            if self.echo {
+                let _ = self.writer.write_str(s);
+            }
+            self.redraw(0, 0);
+        }
+    }
+
+    /// Inserts text at the cursor, writing it out and redrawing the rest of
+    /// the line.
+    fn insert_str(&mut self, s: &str) {
+        // Echo only as much as fits
+        let mut len = s.len().min(self.line.room());
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        for b in s[..len].bytes() {
+            self.line.insert(b);
+        }
+        let _ = self.writer.write_str(&s[..len]);
+        self.redraw(0, 0);
+    }
+
+    /// Completes the word before the cursor. If there is more than one
+    /// possibility, and they have nothing more in common, `list` says
+    /// whether to list them. Returns true if anything was inserted.
+    fn complete(&mut self, list: bool) -> bool {
+        let before = match core::str::from_utf8(self.line.before()) {
+            Ok(before) => before,
+            Err(_) => return false,
+        };
+        // Only the command being typed counts, not any chained before it
+        let before = args::chain(before).last().map_or(before, |(_, command)| command);
+        let start = complete::word_start(before);
+        let partial = &before[start..];
+        if partial.contains(['"', '\'', '\\']) {
+            // Too hard to know what to insert
+            return false;
+        }
+        let mut buf = [0u8; N];
+        let mut argv = [""; MAX_ARGS];
+        let argc = match args::tokenize(&before[..start], &mut buf, &mut argv) {
+            Ok(argc) => argc,
+            Err(_) => return false,
+        };
+        let argv = &argv[..argc];
+
+        let mut completions: Completions<N> = Completions::new(partial, self.matching.ignore_case);
+        offer(&self.commands, &self.macros, argv, self.matching, &mut |s| completions.add(s));
+        let count = completions.count();
+        let mut suffix = [0u8; N];
+        let mut len = completions.suffix().len();
+        suffix[..len].copy_from_slice(completions.suffix().as_bytes());
+        if count == 1 && len < N {
+            // A unique match is a whole word
+            suffix[len] = b' ';
+            len += 1;
+        }
+        let suffix = core::str::from_utf8(&suffix[..len]).unwrap_or_default();
+
+        match count {
+            _ if suffix.len() > self.line.room() => {
+                // Ring the bell, as the completion won't fit
+                let _ = self.writer.write_char('\x07');
+            }
+            _ if !suffix.is_empty() => {
+                self.insert_str(suffix);
+                return true;
+            }
+            n if n > 1 && list => {
+                let writer = &mut self.writer;
+                let _ = writeln!(writer);
+                let matching = self.matching;
+                let mut print = |s: &str| {
+                    if matching.starts(s, partial) {
+                        let _ = write!(writer, "{}  ", s);
+                    }
+                };
+                offer(&self.commands, &self.macros, argv, self.matching, &mut print);
+                let _ = writeln!(self.writer);
+                let _ = self.prompt();
+                let line = core::str::from_utf8(self.line.as_bytes()).unwrap_or_default();
+                let _ = self.writer.write_str(line);
+                let count = self.line.after().chars().count();
+                self.back(count);
+            }
+            _ => {
+                // Ring the bell
+                let _ = self.writer.write_char('\x07');
+            }
+        }
+        false
+    }
+
+    /// Handles a key that sent an escape sequence.
+    fn key(&mut self, key: Key) {
+        match key {
+            Key::Up | Key::Down => self.recall(key),
+            Key::Left => {
+                if self.line.left() {
+                    self.back(1);
+                }
+            }
+            Key::Right => {
+                if let Some(s) = self.line.right() {
+                    let _ = self.writer.write_str(s);
+                }
+            }
+            Key::Home => {
+                let count = self.line.home();
+                self.back(count);
+            }
+            Key::End => {
+                let _ = self.writer.write_str(self.line.after());
+                self.line.end();
+            }
+            Key::Delete => {
+                if self.line.delete() {
+                    self.redraw(0, 1);
+                }
+            }
+        }
+    }
+
+    /// Replaces the line with one from the history.
+    fn recall(&mut self, key: Key) {
+        let age = match (key, self.recalled) {
+            (Key::Up, None) => Some(0),
+            (Key::Up, Some(age)) => Some(age + 1),
+            (Key::Down, Some(0)) => None,
+            (Key::Down, Some(age)) => Some(age - 1),
+            _ => return,
+        };
+        let line = match age {
+            Some(age) => match self.history.get(age) {
+                Some(line) => line,
+                None => return,
+            },
+            None => &[],
+        };
+        self.recalled = age;
+        let _ = self.writer.write_str(self.line.after());
+        let count = self.line.chars();
+        self.line.set(line);
+        self.erase(count);
+        let _ = self.writer.write_str(core::str::from_utf8(self.line.as_bytes()).unwrap_or_default());
+    }
+
+    /// Moves the cursor on the terminal `count` characters left.
+    fn back(&mut self, count: usize) {
+        for _ in 0..count {
+            let _ = self.writer.write_char('\x08');
+        }
+    }
+
+    /// Updates the terminal after an edit. The cursor is moved `back`
+    /// characters left, the rest of the line is written, `erased` spaces
+    /// rub out what was there before, and the cursor is put back.
+    fn redraw(&mut self, back: usize, erased: usize) {
+        if self.line.after().is_empty() && back == erased {
+            self.erase(erased);
+            return;
+        }
+        self.back(back);
+        let tail = self.line.after();
+        let _ = self.writer.write_str(tail);
+        let count = tail.chars().count() + erased;
+        for _ in 0..erased {
+            let _ = self.writer.write_char(' ');
+        }
+        self.back(count);
+    }
+
+    /// Does this `\r` or `\n` end a line?
+    fn ends_line(&self, c: u8) -> bool {
+        match self.input_ending {
+            None => true,
+            Some(LineEnding::Cr) => c == b'\r',
+            Some(LineEnding::Lf) | Some(LineEnding::CrLf) => c == b'\n',
+        }
+    }
+
+    /// Rubs out the last `count` characters on the terminal.
+    fn erase(&mut self, count: usize) {
+        for _ in 0..count {
+            let _ = write!(self.writer, "\x08 \x08");
+        }
+    }
+
+    /// Runs the command line received so far. The line is split into words
+    /// (see `Args`), the first of which names the command to run. An empty
+    /// line does nothing.
+    ///
+    /// Several commands can be chained together on one line: `a; b` runs
+    /// both, `a && b` runs `b` only if `a` succeeds, and `a || b` runs `b`
+    /// only if `a` fails. The result is that of the last command to run.
+    /// `receive_and_print` also prints the error from any command that
+    /// failed before it.
+    pub fn process_with(&mut self, context: &mut T) -> Result<Outcome, Error> {
+        let mut copy = [0u8; N];
+        let len = self.line.as_bytes().len();
+        copy[..len].copy_from_slice(self.line.as_bytes());
+        let overflowed = self.line.overflowed();
+        self.line.clear();
+        self.recalled = None;
+        if overflowed {
+            return Err(Error::LineTooLong);
+        }
+        let line = core::str::from_utf8(&copy[..len]).map_err(|_| Error::InvalidUtf8)?;
+        if !line.trim().is_empty() {
+            self.history.push(line.as_bytes());
+        }
+        self.may_watch = args::chain(line).nth(1).is_none();
+        let result = self.run_line(context, line, &[]);
+        self.may_watch = false;
+        result
+    }
+
+    /// Runs one command line, which may come from a script or a macro rather
+    /// than the user, and may chain several commands together (see
+    /// `process_with`). `$1`, `$2` and so on refer to `args`.
+    fn run_line(&mut self, context: &mut T, line: &str, args: &[&str]) -> Result<Outcome, Error> {
+        if line.len() > N {
+            return Err(Error::LineTooLong);
+        }
+        args::check_chain(line)?;
+        if args::chain(line).nth(1).is_some() {
+            self.may_watch = false;
+        }
+        let mut last = Ok(Outcome::Continue);
+        for (link, command) in args::chain(line) {
+            let wanted = match link {
+                Link::Always => true,
+                Link::IfOk => last.is_ok(),
+                Link::IfFailed => last.is_err(),
+            };
+            if !wanted {
+                continue;
+            }
+            match &last {
+                Err(e) if self.print_errors => {
+                    let _ = writeln!(self.writer, "Error: {}", e);
+                }
+                _ => {}
+            }
+            last = self.run_command(context, command, args);
+            self.failed = last.is_err();
+            if let Ok(Outcome::EndSession | Outcome::RestartSession) = last {
+                break;
+            }
+        }
+        last
+    }
+
+    /// Runs a single command, with no `;`, `&&` or `||` in it.
+    fn run_command(&mut self, context: &mut T, command: &str, args: &[&str]) -> Result<Outcome, Error> {
+        let mut buf = [0u8; N];
+        let mut argv = [""; MAX_ARGS];
+        if let Some(at) = args::after_equals(command) {
+            // Keep the commands a macro runs as they were typed
+            let (head, rest) = buf.split_at_mut(at);
+            let mut words = [""; MAX_ARGS];
+            let mut count = args::tokenize(&command[..at], head, &mut words)?;
+            if count < MAX_ARGS && self.defines_macro(&words[..count]) {
+                let body = args::body(&command[at..], rest)?;
+                if !body.is_empty() {
+                    words[count] = body;
+                    count += 1;
+                }
+                return self.run(context, &words[..count]);
+            }
+        }
+        let scope = Scope { variables: &self.variables, failed: self.failed, args };
+        let argc = args::split(command, &mut buf, &mut argv, Some(&scope))?;
+        self.run(context, &argv[..argc])
+    }
+
+    /// Runs the command named by the first of `argv`, giving it the rest.
+    fn run(&mut self, context: &mut T, argv: &[&str]) -> Result<Outcome, Error> {
+        if let Some(idx) = self.find_macro(argv)? {
+            return self.run_macro(context, idx, &argv[1..]);
+        }
+        let mut words = [""; MAX_ARGS];
+        let count = self.expand(argv, &mut words)?;
+        let words = &words[..count];
+        if words.is_empty() {
+            return Ok(Outcome::Continue);
+        }
+        let mut path = [""; MAX_ARGS];
+        let (cmd, depth) = self.resolve(words, &mut path)?;
+        let path = &path[..depth];
+        let given = &words[depth..];
+        if cmd.is_group() {
+            // Say what the subcommands are
+            return self.print_help_for(path).map(|()| Outcome::Continue);
+        }
+        let params = cmd.checked_params();
+        if params.is_empty() {
+            let args = Args::new(cmd.name, given);
+            return self.call(cmd, context, &args);
+        }
+        if let Handler::Builtin(builtin) = cmd.handler {
+            if builtin.runs_command() {
+                // The command to run takes up the rest of the arguments
+                let checked = &given[..given.len().min(params.len())];
+                param::validate(params, checked, path, self.matching.ignore_case)?;
+                return self.call(cmd, context, &Args::new(cmd.name, given).with_params(params));
+            }
+        }
+        param::validate(params, given, path, self.matching.ignore_case)?;
+        // Spell any choices as the parameter does, then fill in the defaults
+        // of any optional arguments left out
+        let mut filled = [""; MAX_ARGS];
+        for ((slot, value), param) in filled.iter_mut().zip(given).zip(params) {
+            *slot = param.canonical(value);
+        }
+        let mut len = given.len();
+        for param in params.iter().skip(len).take(MAX_ARGS - len) {
+            match param.default_value() {
+                Some(value) => filled[len] = value,
+                None => break,
+            }
+            len += 1;
+        }
+        let args = Args::new(cmd.name, &filled[..len]).with_params(params);
+        self.call(cmd, context, &args)
+    }
+
+    /// Calls a command's handler, or runs it if it is built in.
+    fn call(&mut self, cmd: Command<'a, T>, context: &mut T, args: &Args) -> Result<Outcome, Error> {
+        match cmd.handler {
+            Handler::Builtin(builtin) => self.run_builtin(builtin, context, args),
+            handler => self.commands.call(handler, context, args, &mut self.writer),
+        }
+    }
+
+    /// Runs each line of `script` in turn, as if it had been typed.
+    /// Blank lines, and lines starting with `#`, are skipped. If a command
+    /// fails, what happens depends on `on_error`: either the script stops, or
+    /// the error is printed (with its line number) and the script carries
+    /// on. Either way, the first error is returned.
+    ///
+    /// If a command asks for the session to end or restart, the script stops
+    /// and returns that `Outcome`.
+    ///
+    /// ```
+    /// use harness::{Builtin, Harness, OnError, Outcome};
+    ///
+    /// let mut h = Harness::new(String::new());
+    /// h.set_builtins(&[Builtin::Echo]);
+    /// let script = "# Say hello\necho hello\n\necho world\n";
+    /// assert_eq!(h.run_script(script, OnError::Stop), Ok(Outcome::Continue));
+    /// assert_eq!(h.writer(), "hello\nworld\n");
+    /// ```
+    pub fn run_script_with(&mut self,
+                           context: &mut T,
+                           script: &str,
+                           on_error: OnError)
+                           -> Result<Outcome, ScriptError> {
+        self.may_watch = false;
+        let mut run = script::Run::new(on_error);
+        for (i, line) in script.lines().enumerate() {
+            let result = match script::command(line) {
+                Some(command) => self.run_line(context, command, &[]),
+                None => continue,
+            };
+            if let Some(done) = run.step(i + 1, result, &mut self.writer) {
+                return done;
+            }
+        }
+        run.finish()
+    }
+
+    /// Gives the scripts that the `source` built-in command runs. `source`
+    /// itself must be turned on with `set_builtins`. Scripts run by `source`
+    /// stop at the first error.
+    pub fn set_scripts(&mut self, scripts: &'a dyn Scripts) {
+        self.scripts = Some(scripts);
+    }
+
+    /// Sets a session variable, which `$name` or `${name}` on a command line
+    /// is replaced with. The name must be a letter or underscore followed by
+    /// letters, digits and underscores. There can be up to 8 variables, with
+    /// names of up to 16 bytes and values of up to 32.
+    pub fn set_variable(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        self.variables.set(name, value)
+    }
+
+    /// The value of a session variable, if it is set.
+    pub fn variable(&self, name: &str) -> Option<&str> {
+        self.variables.get(name)
+    }
+
+    /// Removes a session variable. Returns whether it was set.
+    pub fn unset_variable(&mut self, name: &str) -> bool {
+        self.variables.unset(name)
+    }
+
+    /// Defines a macro: a command called `name` that runs the command line
+    /// `commands`, which may chain several commands together and refer to
+    /// the macro's arguments as `$1`, `$2` and so on. A macro hides any
+    /// command with the same name, and can't run itself. There can be up to
+    /// 8 macros, with names of up to 16 bytes and command lines of up to 64,
+    /// or `N` if that is less.
+    pub fn define_macro(&mut self, name: &str, commands: &str) -> Result<(), Error> {
+        self.set_macro(name, &[commands])
+    }
+
+    /// Defines a macro that runs `words` joined with spaces, if they fit on
+    /// a command line.
+    fn set_macro(&mut self, name: &str, words: &[&str]) -> Result<(), Error> {
+        let len = words.iter().map(|word| word.len()).sum::<usize>() + words.len().saturating_sub(1);
+        if len > N {
+            return Err(Error::failed(format_args!("too long for a command line, which holds at most {} bytes",
+                                                  N)));
+        }
+        self.macros.set_words(name, words)
+    }
+
+    /// Runs a built-in command with the arguments given.
+    fn run_builtin(&mut self, builtin: Builtin, context: &mut T, args: &Args) -> Result<Outcome, Error> {
+        match builtin {
+            Builtin::Help => self.print_help_for(args)?,
+            Builtin::Echo => {
+                let mut sep = "";
+                for arg in args.iter() {
+                    write!(self.writer, "{}{}", sep, arg)?;
+                    sep = " ";
+                }
+                writeln!(self.writer)?;
+            }
+            Builtin::Clear => self.writer.write_str(CLEAR_SCREEN).map_err(Error::io("clearing the screen"))?,
+            Builtin::Version => {
+                writeln!(self.writer, "{}", self.version).map_err(Error::io("printing the version"))?
+            }
+            Builtin::History => self.print_history().map_err(Error::io("printing history"))?,
+            Builtin::Exit => return Ok(Outcome::EndSession),
+            Builtin::Source => return self.source(context, args[0]),
+            Builtin::Set => self.variables.set(args[0], args[1])?,
+            Builtin::Unset => {
+                self.variables.unset(args[0]);
+            }
+            Builtin::Alias => self.alias(args)?,
+            Builtin::Repeat => {
+                self.may_watch = false;
+                // The count has been checked against the parameter
+                for _ in 0..args.int("count").unwrap_or_default() {
+                    match self.run(context, &args[1..])? {
+                        Outcome::Continue => {}
+                        outcome => return Ok(outcome),
+                    }
+                }
+            }
+            Builtin::Watch => {
+                let seconds = args.float("seconds").unwrap_or_default();
+                return self.watch(context, seconds, &args[1..]);
+            }
+            Builtin::Env => {
+                for (name, value) in self.variables.iter() {
+                    writeln!(self.writer, "{}={}", name, value).map_err(Error::io("listing variables"))?;
+                }
+            }
+        }
+        Ok(Outcome::Continue)
+    }
+
+    /// Runs the macro at `idx` in `macros`, giving it `args`.
+    fn run_macro(&mut self, context: &mut T, idx: usize, args: &[&str]) -> Result<Outcome, Error> {
+        let (name, commands) = self.macros.at(idx);
+        if self.expanding[idx] {
+            return Err(Error::RecursiveMacro(name.into()));
+        }
+        // The macro may redefine itself as it runs
+        let mut copy = [0u8; vars::MAX_BODY];
+        copy[..commands.len()].copy_from_slice(commands.as_bytes());
+        let commands = core::str::from_utf8(&copy[..commands.len()]).unwrap_or_default();
+        self.expanding[idx] = true;
+        let result = self.run_line(context, commands, args);
+        self.expanding[idx] = false;
+        result
+    }
+
+    /// Starts running `argv` every `seconds`, for the `watch` built-in
+    /// command, and runs it for the first time.
+    fn watch(&mut self, context: &mut T, seconds: f64, argv: &[&str]) -> Result<Outcome, Error> {
+        if !core::mem::replace(&mut self.may_watch, false) {
+            return Err(Error::failed("watch must be the only command on a line typed at the prompt"));
+        }
+        let clock = self.clock.ok_or_else(|| Error::failed("watch needs a clock"))?;
+        let watch = Watch::new(argv, seconds, clock.now_ms())?;
+        self.watch = Some(watch);
+        Ok(self.show_watch(context, &watch))
+    }
+
+    /// Clears the screen and runs the command being watched, printing any
+    /// error. The watch stops if the command ends or restarts the session.
+    fn show_watch(&mut self, context: &mut T, watch: &Watch<N>) -> Outcome {
+        let mut argv = [""; MAX_ARGS];
+        let argc = watch.argv(&mut argv);
+        let argv = &argv[..argc];
+        let _ = self.writer.write_str(CLEAR_SCREEN);
+        let _ = write!(self.writer, "Every {}s:", watch.seconds);
+        for word in argv {
+            let _ = write!(self.writer, " {}", word);
+        }
+        let _ = writeln!(self.writer);
+        let outcome = self.run(context, argv).unwrap_or_else(|e| {
+            let _ = writeln!(self.writer, "Error: {}", e);
+            Outcome::Continue
+        });
+        if outcome != Outcome::Continue {
+            self.watch = None;
+        }
+        outcome
+    }
+
+    /// Gives the clock that the `watch` built-in command tells the time with.
+    /// `watch` itself must be turned on with `set_builtins`.
+    pub fn set_clock(&mut self, clock: &'a dyn Clock) {
+        self.clock = Some(clock);
+    }
+
+    /// Whether a command is being watched (see `poll_with`).
+    pub fn is_watching(&self) -> bool {
+        self.watch.is_some()
+    }
+
+    /// Runs the command being watched with the `watch` built-in command
+    /// again, if it is time to. Call this often, such as whenever no input
+    /// is waiting. Returns what should happen next, if the command ran; any
+    /// error is printed. A key received by `receive_with` stops the watch.
+    pub fn poll_with(&mut self, context: &mut T) -> Option<Outcome> {
+        let now = self.clock?.now_ms();
+        let mut watch = self.watch?;
+        if !watch.due(now) {
+            return None;
+        }
+        self.watch = Some(watch);
+        Some(self.show_watch(context, &watch))
+    }
+
+    /// Defines, shows or lists macros, for the `alias` built-in command.
+    fn alias(&mut self, args: &[&str]) -> Result<(), Error> {
+        match args {
+            [] => {
+                for (name, commands) in self.macros.iter() {
+                    writeln!(self.writer, "{} = {}", name, commands).map_err(Error::io("listing macros"))?;
+                }
+            }
+            [name] => {
+                let commands = self.macros
+                    .get(name)
+                    .ok_or_else(|| Error::failed(format_args!("no macro called {}", name)))?;
+                writeln!(self.writer, "{} = {}", name, commands).map_err(Error::io("listing macros"))?;
+            }
+            [name, "=", commands @ ..] if !commands.is_empty() => self.set_macro(name, commands)?,
+            _ => return Err(Error::failed("expected alias <name> = <commands>")),
+        }
+        Ok(())
+    }
+
+    /// Runs the script called `name`, for the `source` built-in command.
+    fn source(&mut self, context: &mut T, name: &str) -> Result<Outcome, Error> {
+        let script = self.scripts
+            .and_then(|scripts| scripts.script(name))
+            .ok_or_else(|| Error::failed(format_args!("no script called {}", name)))?;
+        if self.script_depth == script::MAX_DEPTH {
+            return Err(Error::NestedTooDeep);
+        }
+        self.script_depth += 1;
+        let result = self.run_script_with(context, script, OnError::Stop);
+        self.script_depth -= 1;
+        match result {
+            // Keep the error as it is if it happened in a script this one ran
+            Err(ScriptError { error: Error::NestedTooDeep, .. }) => Err(Error::NestedTooDeep),
+            #[cfg(feature = "std")]
+            Err(ScriptError { line, error }) => {
+                Err(Error::Script { name: name.into(), line, error: Box::new(error) })
+            }
+            #[cfg(not(feature = "std"))]
+            Err(ScriptError { error, .. }) => Err(error),
+            Ok(outcome) => Ok(outcome),
+        }
+    }
 }
 
-pub struct Harness<'a, W> {
-    cmdline: Vec<u8>,
-    commands: HashMap<&'a str, Command<'a>>,
-    writer: W,
-}
+#[cfg(feature = "std")]
+impl<'a, W, T, const N: usize, const H: usize> Harness<'a, W, T, N, H>
+    where W: Write
+{
+    /// Registers a command. The handler can be a function or a closure, and
+    /// is given the arguments the command was called with and a writer for
+    /// its output.
+    ///
+    /// Returns the new command, so that it can be set up further, e.g. with
+    /// `Command::completion`.
+    pub fn add_command<F>(&mut self,
+                          cmd_name: &'a str,
+                          help_text: &'a str,
+                          mut handler: F)
+                          -> &mut Command<'a, T>
+        where F: FnMut(&Args, &mut dyn Write) -> CommandResult + 'a
+    {
+        self.add_context_command(cmd_name,
+                                 help_text,
+                                 move |_: &mut T, args: &Args, out: &mut dyn Write| {
+                                     handler(args, out)
+                                 })
+    }
+
+    /// Registers a command whose handler is also given the context passed to
+    /// `receive_with` or `process_with`.
+    pub fn add_context_command<F>(&mut self,
+                                  cmd_name: &'a str,
+                                  help_text: &'a str,
+                                  handler: F)
+                                  -> &mut Command<'a, T>
+        where F: FnMut(&mut T, &Args, &mut dyn Write) -> CommandResult + 'a
+    {
+        let mut handler = handler;
+        self.commands.add(cmd_name,
+                          help_text,
+                          Box::new(move |context: &mut T, args: &Args, out: &mut dyn Write| {
+                                       handler(context, args, out).map(|()| Outcome::Continue)
+                                   }))
+    }
+
+    /// Registers a command whose handler says what should happen next (see
+    /// `Command::with_outcome`).
+    pub fn add_outcome_command<F>(&mut self,
+                                  cmd_name: &'a str,
+                                  help_text: &'a str,
+                                  handler: F)
+                                  -> &mut Command<'a, T>
+        where F: FnMut(&mut T, &Args, &mut dyn Write) -> Result<Outcome, Error> + 'a
+    {
+        self.commands.add(cmd_name, help_text, Box::new(handler))
+    }
+
+    /// Adds an alias for a command (see `set_aliases`), replacing any alias
+    /// with the same name.
+    pub fn add_alias(&mut self, alias: &'a str, command: &'a str) {
+        self.commands.add_alias(alias, command);
+    }
+
+    /// Like `run_script_with`, but reads the script from `source`, such as a
+    /// `File`. If the script can't be read, it stops there.
+    pub fn run_script_from_with<R>(&mut self,
+                                   context: &mut T,
+                                   source: R,
+                                   on_error: OnError)
+                                   -> Result<Outcome, ScriptError>
+        where R: std::io::Read
+    {
+        use std::io::BufRead;
+        let mut run = script::Run::new(on_error);
+        for (i, line) in std::io::BufReader::new(source).lines().enumerate() {
+            let line = line.map_err(|e| {
+                ScriptError {
+                    line: i + 1,
+                    error: Error::failed(format_args!("reading script: {}", e)),
+                }
+            })?;
+            let result = match script::command(&line) {
+                Some(command) => self.run_line(context, command, &[]),
+                None => continue,
+            };
+            if let Some(done) = run.step(i + 1, result, &mut self.writer) {
+                return done;
+            }
+        }
+        run.finish()
+    }
+
+    /// Makes a built-in command available as `name`, replacing any command
+    /// with that name.
+    pub fn add_builtin(&mut self, name: &'a str, builtin: Builtin) -> &mut Command<'a, T> {
+        self.commands.add_builtin(name, builtin)
+    }
+
+    /// Registers an empty group of commands. Commands are added to it by
+    /// giving `add_command` a name starting with the group's name, e.g.
+    ///
+    /// ```
+    /// # use std::fmt::Write;
+    /// # use harness::Args;
+    /// let mut h = harness::Harness::new(String::new());
+    /// h.add_group("net", "Networking");
+    /// h.add_group("net ip", "IP settings");
+    /// h.add_command("net ip set", "Sets the IP address", |args: &Args, out: &mut dyn Write| {
+    ///     writeln!(out, "IP is {}", args[0])?;
+    ///     Ok(())
+    /// });
+    /// for b in b"net ip set 10.0.0.2\n".iter() {
+    ///     h.receive(*b);
+    /// }
+    /// assert_eq!(h.writer(), "IP is 10.0.0.2\n");
+    /// ```
+    ///
+    /// Commands can also be added to a group from the table given to
+    /// `with_commands` in the same way.
+    pub fn add_group(&mut self, group_name: &'a str, help_text: &'a str) -> &mut Command<'a, T> {
+        self.commands.add_group(group_name, help_text)
+    }
+}
+
+/// Convenience methods for a `Harness` whose commands need no context.
+impl<'a, W, const N: usize, const H: usize> Harness<'a, W, (), N, H>
+    where W: Write
+{
+    pub fn receive_and_print(&mut self, c: u8) -> Result<Outcome, fmt::Error> {
+        self.receive_and_print_with(&mut (), c)
+    }
+
+    pub fn receive(&mut self, c: u8) -> Option<Result<Outcome, Error>> {
+        self.receive_with(&mut (), c)
+    }
+
+    pub fn process(&mut self) -> Result<Outcome, Error> {
+        self.process_with(&mut ())
+    }
+
+    pub fn poll(&mut self) -> Option<Outcome> {
+        self.poll_with(&mut ())
+    }
+
+    pub fn run_script(&mut self, script: &str, on_error: OnError) -> Result<Outcome, ScriptError> {
+        self.run_script_with(&mut (), script, on_error)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn run_script_from<R>(&mut self, source: R, on_error: OnError) -> Result<Outcome, ScriptError>
+        where R: std::io::Read
+    {
+        self.run_script_from_with(&mut (), source, on_error)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[allow(clippy::char_lit_as_u8)]
+mod tests {
+    use super::{Args, Builtin, Command, CommandResult, Error, LineEnding, Outcome, Param};
+    use core::fmt::Write;
+
+    fn works(_args: &Args, out: &mut dyn Write) -> CommandResult {
+        writeln!(out, "Works!").unwrap();
+        Ok(())
+    }
+
+    fn fails(_args: &Args, out: &mut dyn Write) -> CommandResult {
+        writeln!(out, "Fails!").unwrap();
+        Err("boom".into())
+    }
+
+    fn led(args: &Args, _out: &mut dyn Write) -> CommandResult {
+        match &args[..] {
+            ["3", "on"] => Ok(()),
+            ["3", "with spaces"] => Ok(()),
+            _ => Err("bad args".into()),
+        }
+    }
+
+    struct Motor {
+        speed: u32,
+    }
+
+    fn set_speed(motor: &mut Motor, args: &Args, _out: &mut dyn Write) -> CommandResult {
+        let speed = args.first().ok_or("missing speed")?;
+        motor.speed = speed.parse().map_err(|_| "bad speed")?;
+        Ok(())
+    }
+
+    /// What `help` lists for the default built-in commands, when the
+    /// longest command name is no longer than `history`.
+    const BUILTIN_HELP: &str = "  help     Lists the commands, or describes one\n  history  Lists the commands entered so \
+                                far\n";
+
+    fn feed<W: Write>(h: &mut super::Harness<W>, line: &str) -> Option<Result<Outcome, Error>> {
+        let mut result = None;
+        for b in line.bytes() {
+            result = h.receive(b);
+        }
+        result
+    }
+
+    #[test]
+    fn bad_command() {
+
+        let outbuf = String::new();
+        let mut h = super::Harness::new(outbuf);
+        h.add_command("foobar", "test function", works);
+        assert_eq!(h.receive('h' as u8), None);
+        assert_eq!(h.receive('h' as u8), None);
+        assert_eq!(h.receive('h' as u8), None);
+        assert_eq!(h.receive('h' as u8), None);
+        assert_eq!(h.receive('\n' as u8), Some(Err(unknown("hhhh", &[]))));
+    }
+
+    #[test]
+    fn good_command() {
+        let outbuf = String::new();
+        let mut h = super::Harness::new(outbuf);
+        h.add_command("foo", "Does stuff.", works);
+        assert_eq!(h.receive('f' as u8), None);
+        assert_eq!(h.receive('o' as u8), None);
+        assert_eq!(h.receive('o' as u8), None);
+        assert_eq!(h.receive('\n' as u8), Some(Ok(Outcome::Continue)));
+    }
+
+    #[test]
+    fn good_command_but_fails() {
+        let outbuf = String::new();
+        let mut h = super::Harness::new(outbuf);
+        h.add_command("foo", "Does stuff.", fails);
+        assert_eq!(h.receive('f' as u8), None);
+        assert_eq!(h.receive('o' as u8), None);
+        assert_eq!(h.receive('o' as u8), None);
+        assert_eq!(h.receive('\n' as u8), Some(Err("boom".into())));
+    }
+
+    #[test]
+    fn good_command_twice() {
+        let outbuf = String::new();
+        let mut h = super::Harness::new(outbuf);
+        h.add_command("foo", "Does stuff.", works);
+        assert_eq!(h.receive('f' as u8), None);
+        assert_eq!(h.receive('o' as u8), None);
+        assert_eq!(h.receive('o' as u8), None);
+        assert_eq!(h.receive('\n' as u8), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.receive('f' as u8), None);
+        assert_eq!(h.receive('o' as u8), None);
+        assert_eq!(h.receive('o' as u8), None);
+        assert_eq!(h.receive('\n' as u8), Some(Ok(Outcome::Continue)));
+    }
+
+    #[test]
+    fn help() {
+        let outbuf = String::new();
+        let mut h = super::Harness::new(outbuf);
+        assert_eq!(h.receive('h' as u8), None);
+        assert_eq!(h.receive('e' as u8), None);
+        assert_eq!(h.receive('l' as u8), None);
+        assert_eq!(h.receive('p' as u8), None);
+        assert_eq!(h.receive('\n' as u8), Some(Ok(Outcome::Continue)));
+    }
+
+    #[test]
+    fn arguments() {
+        let outbuf = String::new();
+        let mut h = super::Harness::new(outbuf);
+        h.add_command("led", "Controls an LED.", led);
+        assert_eq!(feed(&mut h, "led 3 on\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "  led   3  on \n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "led 3 \"with spaces\"\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "led 3 with\\ spaces\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "led 3 off\n"), Some(Err("bad args".into())));
+        assert_eq!(feed(&mut h, "led 3 'on\n"), Some(Err(Error::UnterminatedQuote)));
+    }
+
+    #[test]
+    fn empty_line() {
+        let outbuf = String::new();
+        let mut h = super::Harness::new(outbuf);
+        assert_eq!(feed(&mut h, "\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "   \n"), Some(Ok(Outcome::Continue)));
+    }
+
+    #[test]
+    fn closure_command() {
+        let mut count = 0;
+        {
+            let outbuf = String::new();
+            let mut h = super::Harness::new(outbuf);
+            h.add_command("inc", "Counts.", |args: &Args, _: &mut dyn Write| {
+                count += args.len() + 1;
+                Ok(())
+            });
+            assert_eq!(feed(&mut h, "inc\n"), Some(Ok(Outcome::Continue)));
+            assert_eq!(feed(&mut h, "inc a b\n"), Some(Ok(Outcome::Continue)));
+        }
+        assert_eq!(count, 4);
+    }
+
+    #[test]
+    fn context_command() {
+        let outbuf = String::new();
+        let mut motor = Motor { speed: 0 };
+        let mut h = super::Harness::new(outbuf);
+        h.add_context_command("set_speed", "Sets the motor speed.", set_speed);
+        h.add_command("foo", "Does stuff.", works);
+        for b in b"set_speed 100".iter() {
+            assert_eq!(h.receive_with(&mut motor, *b), None);
+        }
+        assert_eq!(h.receive_with(&mut motor, b'\n'), Some(Ok(Outcome::Continue)));
+        assert_eq!(motor.speed, 100);
+        for b in b"foo".iter() {
+            assert_eq!(h.receive_with(&mut motor, *b), None);
+        }
+        assert_eq!(h.receive_with(&mut motor, b'\n'), Some(Ok(Outcome::Continue)));
+        for b in b"set_speed fast".iter() {
+            assert_eq!(h.receive_with(&mut motor, *b), None);
+        }
+        assert_eq!(h.process_with(&mut motor), Err("bad speed".into()));
+        assert_eq!(motor.speed, 100);
+    }
+
+    #[test]
+    fn handler_output() {
+        let outbuf = String::new();
+        let mut h = super::Harness::new(outbuf);
+        h.add_command("foo", "Does stuff.", works);
+        h.add_command("bar", "Does other stuff.", fails);
+        for b in b"foo\nbar\n".iter() {
+            h.receive_and_print(*b).unwrap();
+        }
+        assert_eq!(h.writer(), "Works!\n> Fails!\nError: boom\n> ");
+    }
 
-impl<'a, W> Harness<'a, W>
-    where W: Write
-{
-    pub fn new(writer: W) -> Harness<'a, W> {
-        Harness {
-            cmdline: Vec::new(),
-            commands: HashMap::new(),
-            writer: writer,
+    static TABLE: [Command<Motor>; 2] = [Command::new("foo", "Does stuff.", works),
+                                         Command::with_context("set_speed",
+                                                               "Sets the motor speed.",
+                                                               set_speed)];
+
+    #[test]
+    fn static_table() {
+        let mut motor = Motor { speed: 0 };
+        let mut h = super::Harness::with_commands(String::new(), &TABLE);
+        for b in b"set_speed 10\nfoo\n".iter() {
+            h.receive_and_print_with(&mut motor, *b).unwrap();
+        }
+        assert_eq!(motor.speed, 10);
+        assert_eq!(h.writer(), "> Works!\n> ");
+    }
+
+    #[test]
+    fn registered_hides_table() {
+        let mut motor = Motor { speed: 0 };
+        let mut h = super::Harness::with_commands(String::new(), &TABLE);
+        h.add_command("foo", "Does other stuff.", fails);
+        for b in b"foo\nhelp\n".iter() {
+            h.receive_and_print_with(&mut motor, *b).unwrap();
+        }
+        assert_eq!(h.writer(),
+                   "Fails!\nError: boom\n>   foo        Does other stuff.\n  set_speed  Sets the motor \
+                    speed.\n  help       Lists the commands, or describes one\n  history    Lists the \
+                    commands entered so far\n> ");
+    }
+
+    #[test]
+    fn line_too_long() {
+        let mut h: super::Harness<String, (), 8> = super::Harness::sized(String::new(), &[]);
+        h.add_command("foo", "Does stuff.", works);
+        for b in b"foo 1234567".iter() {
+            assert_eq!(h.receive(*b), None);
+        }
+        assert_eq!(h.receive(b'\n'), Some(Err(Error::LineTooLong)));
+        for b in b"foo 1234".iter() {
+            assert_eq!(h.receive(*b), None);
+        }
+        assert_eq!(h.receive(b'\n'), Some(Ok(Outcome::Continue)));
+    }
+
+    #[test]
+    fn line_editing() {
+        let mut h = super::Harness::new(String::new());
+        h.add_command("foo", "Does stuff.", works);
+        assert_eq!(feed(&mut h, "fox\x08o\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "fox\x7fo\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "bar baz\x17\x17foo\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "bar baz\x15foo\n"), Some(Ok(Outcome::Continue)));
+        let rubout = |n| "\x08 \x08".repeat(n);
+        assert_eq!(*h.writer(),
+                   format!("{}Works!\n{}Works!\n{}Works!\n{}Works!\n",
+                           rubout(1),
+                           rubout(1),
+                           rubout(7),
+                           rubout(7)));
+    }
+
+    #[test]
+    fn ctrl_c() {
+        let mut h = super::Harness::new(String::new());
+        h.add_command("foo", "Does stuff.", fails);
+        for b in b"foo\x03".iter() {
+            h.receive_and_print(*b).unwrap();
+        }
+        assert_eq!(h.receive(b'\n'), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "^C\n> ");
+    }
+
+    #[test]
+    fn echo() {
+        let mut h = super::Harness::new(String::new());
+        h.add_command("foo", "Does stuff.", works);
+        h.set_echo(true);
+        h.prompt().unwrap();
+        for b in "fox\x7fo \u{e9}\x17\n".bytes() {
+            h.receive_and_print(b).unwrap();
+        }
+        assert_eq!(h.writer(),
+                   "> fox\x08 \x08o \u{e9}\x08 \x08\nWorks!\n> ");
+    }
+
+    #[test]
+    fn input_line_endings() {
+        let mut h = super::Harness::new(String::new());
+        h.add_command("foo", "Does stuff.", works);
+        for b in b"foo\rfoo\r\nfoo\n\nfoo\r\r".iter() {
+            h.receive_and_print(*b).unwrap();
+        }
+        assert_eq!(h.writer(), "Works!\n> Works!\n> Works!\n> > Works!\n> > ");
+        h.writer_mut().clear();
+        h.set_input_line_ending(Some(LineEnding::Lf));
+        for b in b"foo\r\nfoo\r".iter() {
+            h.receive_and_print(*b).unwrap();
         }
+        assert_eq!(h.writer(), "Works!\n> ");
+        assert_eq!(h.receive(b'\n'), Some(Ok(Outcome::Continue)));
     }
 
-    pub fn print_help(&mut self) -> Result<(), std::io::Error> {
-        for (cmd_name, cmd) in self.commands.iter() {
-            try!(write!(self.writer, "Command: {} - {}\n", cmd_name, cmd.help_text))
+    #[test]
+    fn output_line_endings() {
+        let mut h = super::Harness::new(String::new());
+        h.add_command("foo", "Does stuff.", fails);
+        h.set_output_line_ending(LineEnding::CrLf);
+        for b in b"foo\r\nhelp\r\n".iter() {
+            h.receive_and_print(*b).unwrap();
         }
+        assert_eq!(h.writer(),
+                   "Fails!\r\nError: boom\r\n>   foo      Does stuff.\r\n  help     Lists the commands, or \
+                    describes one\r\n  history  Lists the commands entered so far\r\n> ");
+    }
+
+    fn record(seen: &mut Vec<String>, args: &Args, _out: &mut dyn Write) -> CommandResult {
+        seen.push(args.join(" "));
         Ok(())
     }
 
-    pub fn prompt(&mut self) -> Result<(), std::io::Error> {
-        try!(write!(self.writer, "> "));
-        try!(self.writer.flush());
+    #[test]
+    fn history() {
+        let mut seen = Vec::new();
+        let mut h = super::Harness::new(String::new());
+        h.add_context_command("foo", "Does stuff.", record);
+        // Up, up, up (no more history), down
+        for b in b"foo 1\nfoo 2\n\nx\x1b[A\x1b[A\x1b[A\x1bOB\n".iter() {
+            h.receive_with(&mut seen, *b);
+        }
+        assert_eq!(seen, ["1", "2", "2"]);
+        // Down past the most recent gives an empty line
+        for b in b"\x1b[A\x1b[A\x1b[B\x1b[Bfoo 3\n\x1b[A\x1b[A\n".iter() {
+            h.receive_with(&mut seen, *b);
+        }
+        assert_eq!(seen, ["1", "2", "2", "3", "2"]);
+        h.writer_mut().clear();
+        for b in b"history\n".iter() {
+            h.receive_with(&mut seen, *b);
+        }
+        assert_eq!(h.writer(), "    1  foo 1\n    2  foo 2\n    3  foo 3\n    4  foo 2\n    5  history\n");
+    }
+
+    #[test]
+    fn history_redraw() {
+        let mut h = super::Harness::new(String::new());
+        h.set_echo(true);
+        assert_eq!(feed(&mut h, "ab\n"), Some(Err(unknown("ab", &[]))));
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "xyz\x1b[A"), None);
+        assert_eq!(h.writer(), "xyz\x08 \x08\x08 \x08\x08 \x08ab");
+    }
+
+    #[test]
+    fn cursor_editing() {
+        let mut h = super::Harness::new(String::new());
+        h.add_command("led", "Controls an LED.", led);
+        assert_eq!(feed(&mut h, "led on\x1b[D\x1b[D3 \n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "xled 3 on\x1b[H\x1b[3~\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "ed 3 on\x01l\x05\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "led 3\x1b[15~ \x1b[5~on\x1b[1;5D\x1b[C\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "led 3 x on\x1b[D\x1b[D\x1b[D\x7f\x7f\n"), Some(Ok(Outcome::Continue)));
+    }
+
+    #[test]
+    fn cursor_redraw() {
+        let mut h = super::Harness::new(String::new());
+        h.set_echo(true);
+        assert_eq!(feed(&mut h, "ab\x1b[DX"), None);
+        assert_eq!(h.writer(), "ab\x08Xb\x08");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "\x7f\x1b[H\x1b[3~\x05"), None);
+        assert_eq!(h.writer(), "\x08b \x08\x08\x08b \x08\x08b");
+    }
+
+    fn complete_led(args: &Args, add: &mut dyn FnMut(&str)) {
+        if args.len() == 1 {
+            ["on", "off"].iter().for_each(|s| add(s));
+        }
+    }
+
+    #[test]
+    fn complete_command() {
+        let mut h = super::Harness::new(String::new());
+        h.add_command("foobar", "Does stuff.", works);
+        h.add_command("fails", "Doesn't.", fails);
+        assert_eq!(feed(&mut h, "foo\t\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "bar Works!\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "hi\t"), None);
+        assert_eq!(h.writer(), "story ");
+    }
+
+    #[test]
+    fn complete_args() {
+        let mut h = super::Harness::new(String::new());
+        h.add_command("led", "Sets an LED.", led).completion(complete_led);
+        assert_eq!(feed(&mut h, "led 3 o\t"), None);
+        assert_eq!(h.writer(), "\x07");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "n\t\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), " ");
+
+        static TABLE: [Command; 1] =
+            [Command::new("led", "Sets an LED.", led).with_completion(complete_led)];
+        let mut h = super::Harness::with_commands(String::new(), &TABLE);
+        assert_eq!(feed(&mut h, "l\t3 of\t\n"), Some(Err("bad args".into())));
+        assert_eq!(h.writer(), "ed f ");
+    }
+
+    #[test]
+    fn complete_list() {
+        let mut h = super::Harness::new(String::new());
+        h.add_command("start", "Starts.", works);
+        h.add_command("status", "Reports.", works);
+        h.add_command("stop", "Stops.", works);
+        assert_eq!(feed(&mut h, "s x\x01\x1b[C\t"), None);
+        assert_eq!(h.writer(), "\x08\x08\x08st x\x08\x08");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "\t"), None);
+        assert_eq!(h.writer(), "\x07");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "\t"), None);
+        assert_eq!(h.writer(), "\nstart  status  stop  \n> st x\x08\x08");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "o\t"), None);
+        assert_eq!(h.writer(), " x\x08\x08p  x\x08\x08");
+    }
+
+    static IP: [Command; 2] = [Command::new("set", "Sets the IP address.", led),
+                               Command::new("show", "Shows the IP address.", works)];
+    static NET: [Command; 2] = [Command::group("ip", "IP settings.", &IP),
+                                Command::new("up", "Brings the link up.", works)];
+    static GROUPS: [Command; 2] = [Command::group("net", "Networking.", &NET),
+                                   Command::new("foo", "Does stuff.", works)];
+
+    #[test]
+    fn groups() {
+        let mut h = super::Harness::with_commands(String::new(), &GROUPS);
+        assert_eq!(feed(&mut h, "net ip set 3 on\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "net up\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "Works!\n");
+        assert_eq!(feed(&mut h, "net ip frob\n"), Some(Err(unknown_sub("frob", &[]))));
+        assert_eq!(feed(&mut h, "ip\n"), Some(Err(unknown("ip", &[]))));
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "help net\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "  ip  IP settings.\n  up  Brings the link up.\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "net ip\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(),
+                   "  set   Sets the IP address.\n  show  Shows the IP address.\n");
+        assert_eq!(feed(&mut h, "help net down\n"), Some(Err(unknown_sub("down", &[]))));
+    }
+
+    #[test]
+    fn registered_groups() {
+        let mut h = super::Harness::with_commands(String::new(), &GROUPS);
+        h.add_command("net down", "Takes the link down.", fails);
+        h.add_group("gpio", "GPIO pins.");
+        h.add_command("gpio  set", "Sets a pin.", works);
+        assert_eq!(feed(&mut h, "net down\n"), Some(Err("boom".into())));
+        assert_eq!(feed(&mut h, "gpio set\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "set\n"), Some(Err(unknown("set", &["net"]))));
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "help net\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(),
+                   "  ip    IP settings.\n  up    Brings the link up.\n  down  Takes the link down.\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "help\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(),
+                   &("  net      Networking.\n  foo      Does stuff.\n  gpio     GPIO pins.\n".to_string()
+                     + BUILTIN_HELP));
+    }
+
+    #[test]
+    fn complete_subcommand() {
+        let mut h = super::Harness::with_commands(String::new(), &GROUPS);
+        assert_eq!(feed(&mut h, "ne\ti\tsh\t"), None);
+        assert_eq!(h.writer(), "t p ow ");
+    }
+
+    #[test]
+    fn complete_chained() {
+        let mut h = super::Harness::new(String::new());
+        h.add_command("foo", "Does stuff.", works);
+        h.add_command("bar", "Doesn't.", fails);
+        h.set_builtins(&[Builtin::Alias]);
+        assert_eq!(feed(&mut h, "foo; ba\t&& fo\t"), None);
+        assert_eq!(h.writer(), "r o ");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "\x15alias foo = bar\n"), Some(Ok(Outcome::Continue)));
+        h.writer_mut().clear();
+        // The macro and the command it hides are the same word
+        assert_eq!(feed(&mut h, "fo\t"), None);
+        assert_eq!(h.writer(), "o ");
+    }
+
+    #[test]
+    fn complete_ignoring_case() {
+        let mut h = super::Harness::new(String::new());
+        h.add_command("start", "Starts.", works);
+        h.add_command("status", "Reports.", works);
+        h.set_ignore_case(true);
+        assert_eq!(feed(&mut h, "STAR\t"), None);
+        assert_eq!(h.writer(), "t ");
+        assert_eq!(feed(&mut h, "\x15"), None);
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "ST\t\t\t"), None);
+        assert_eq!(h.writer(), "a\x07\nstart  status  \n> STa");
+    }
+
+    #[test]
+    fn complete_too_long() {
+        let mut h: super::Harness<String, (), 8> = super::Harness::sized(String::new(), &[]);
+        h.add_command("abcdefgh", "Does stuff.", works);
+        assert_eq!(h.receive(b'a'), None);
+        assert_eq!(h.receive(b'b'), None);
+        assert_eq!(h.receive(b'\t'), None);
+        assert_eq!(h.writer(), "\x07");
+        assert_eq!(h.line.as_bytes(), b"ab");
+        assert_eq!(h.receive(b'\n'), Some(Err(unknown("ab", &[]))));
+    }
+
+    const PWM: [Param; 3] = [Param::int_range("channel", 0, 3),
+                             Param::int_range("duty", 0, 100),
+                             Param::choice("mode", &["fast", "slow"]).default("fast")];
+
+    fn pwm(args: &Args, out: &mut dyn Write) -> CommandResult {
+        writeln!(out,
+                 "{} {} {}",
+                 args.int("channel").unwrap(),
+                 args.int("duty").unwrap(),
+                 args.get("mode").unwrap())
+            .unwrap();
         Ok(())
     }
 
-    pub fn add_command(&mut self,
-                       cmd_name: &'a str,
-                       help_text: &'a str,
-                       handler: fn() -> Result<(), &'static str>) {
-        let c = Command {
-            help_text: help_text,
-            handler: handler,
-        };
-        let _ = self.commands.insert(cmd_name, c);
+    #[test]
+    fn params() {
+        let mut h = super::Harness::new(String::new());
+        h.add_group("led", "LEDs.");
+        h.add_command("led pwm", "Dims an LED.", pwm).params(&PWM);
+        assert_eq!(feed(&mut h, "led pwm 1 50\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "led pwm 2 100 slow\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "1 50 fast\n2 100 slow\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "led pwm 1 150\n"),
+                   Some(Err(Error::InvalidArgument {
+                       problem: "arg 2 (duty): 150 out of range 0..=100".into(),
+                       usage: "Usage: led pwm <channel> <duty> [mode]".into(),
+                   })));
+        assert_eq!(h.writer(), "");
+        assert_eq!(feed(&mut h, "led pwm 1 2 s\t"), None);
+        assert_eq!(h.writer(), "low ");
     }
 
-    pub fn receive_and_print(&mut self, c: u8) -> Result<(), std::io::Error> {
-        match self.receive(c) {
-            None => Ok(()),
-            Some(Ok(_)) => self.prompt(),
-            Some(Err(s)) => {
-                try!(write!(self.writer, "Error: {}\n", s));
-                self.prompt()
-            }
+    #[test]
+    fn ignore_case() {
+        let mut h = super::Harness::with_commands(String::new(), &GROUPS);
+        h.add_command("status", "Reports.", works);
+        h.add_command("start", "Starts.", works);
+        h.add_group("led", "LEDs.");
+        h.add_command("led pwm", "Dims an LED.", pwm).params(&PWM);
+        assert_eq!(feed(&mut h, "STATUS\n"), Some(Err(unknown("STATUS", &[]))));
+        assert_eq!(feed(&mut h, "led pwm 1 2 SLOW\n"),
+                   Some(Err(Error::InvalidArgument {
+                       problem: "arg 3 (mode): SLOW is not one of fast, slow".into(),
+                       usage: "Usage: led pwm <channel> <duty> [mode]".into(),
+                   })));
+        h.set_ignore_case(true);
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "STATUS\nNet IP Set 3 on\nLED PWM 1 2 SLOW\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "Works!\n1 2 slow\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "HELP NET IP SHOW\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "Command: net ip show - Shows the IP address.\n");
+        assert_eq!(feed(&mut h, "STAUTS\n"), Some(Err(unknown("STAUTS", &["status", "start"]))));
+        h.set_prefix_matching(true);
+        assert_eq!(feed(&mut h, "STA\n"),
+                   Some(Err(Error::Ambiguous("STA matches start, status".into()))));
+    }
+
+    #[test]
+    fn command_help() {
+        const PARAMS: [Param; 2] = [Param::int_range("channel", 0, 3).help("Which LED"),
+                                    Param::choice("mode", &["fast", "slow"]).default("fast")];
+        let mut h = super::Harness::new(String::new());
+        h.add_group("led", "LEDs.");
+        h.add_command("led blink", "Blinks an LED.", works)
+            .params(&PARAMS)
+            .description("Blinks until told to stop.\nUse `led off` to stop.\n")
+            .examples(&["led blink 2", "led blink 0 slow"]);
+        h.add_command("led off", "Turns LEDs off.", works);
+        assert_eq!(feed(&mut h, "help led blink\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(),
+                   "Command: led blink - Blinks an LED.\nBlinks until told to stop.\nUse `led off` to \
+                    stop.\nUsage: led blink <channel> [mode]\nArguments:\n  channel  Which LED \
+                    (0..=3)\n  mode     one of fast, slow; default fast\nExamples:\n  led blink 2\n  \
+                    led blink 0 slow\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "help led off\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "Command: led off - Turns LEDs off.\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "help\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), &("  led      LEDs.\n".to_string() + BUILTIN_HELP));
+    }
+
+    #[test]
+    fn help_listing() {
+        let mut h = super::Harness::new(String::new());
+        h.add_command("stop", "Stops the motor, then waits for it to come to rest.", works);
+        h.add_command("start", "Starts the motor.", works).category("Motor");
+        h.add_command("reset", "Resets.", works);
+        h.set_help_order(super::HelpOrder::Alphabetical);
+        h.set_help_width(40);
+        assert_eq!(feed(&mut h, "help\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(),
+                   "  help     Lists the commands, or\n           describes one\n  history  Lists the \
+                    commands entered so\n           far\n  reset    Resets.\n  stop     Stops the motor, \
+                    then waits\n           for it to come to rest.\n\nMotor:\n  start    Starts the \
+                    motor.\n");
+    }
+
+    #[test]
+    fn aliases() {
+        static ALIASES: [(&str, &str); 2] = [("?", "help"), ("ip", "net ip")];
+        let mut h = super::Harness::with_commands(String::new(), &GROUPS);
+        h.set_aliases(&ALIASES);
+        h.add_alias("f", "foo");
+        assert_eq!(feed(&mut h, "f\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "ip set 3 on\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "ip frob\n"), Some(Err(unknown_sub("frob", &[]))));
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "? ip\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "  set   Sets the IP address.\n  show  Shows the IP address.\n");
+        assert_eq!(feed(&mut h, "fo\n"), Some(Err(unknown("fo", &["f", "foo"]))));
+    }
+
+    #[test]
+    fn prefix_matching() {
+        let mut h = super::Harness::with_commands(String::new(), &GROUPS);
+        h.add_command("stop", "Stops.", works);
+        h.add_command("status", "Reports.", fails);
+        h.add_command("start", "Starts.", works);
+        h.add_command("net down", "Takes the link down.", works);
+        assert_eq!(feed(&mut h, "sta\n"), Some(Err(unknown("sta", &["start", "stop"]))));
+        h.set_prefix_matching(true);
+        assert_eq!(feed(&mut h, "stat\n"), Some(Err("boom".into())));
+        assert_eq!(feed(&mut h, "f\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "n i se 3 on\n"), Some(Ok(Outcome::Continue)));
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "st\n"),
+                   Some(Err(Error::Ambiguous("st matches start, status, stop".into()))));
+        assert_eq!(feed(&mut h, "net ip s\n"), Some(Err(Error::Ambiguous("s matches set, show".into()))));
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "help ne d\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "Command: net down - Takes the link down.\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "h\n"), Some(Err(Error::Ambiguous("h matches help, history".into()))));
+    }
+
+    #[test]
+    fn suggestions() {
+        let mut h = super::Harness::with_commands(String::new(), &GROUPS);
+        h.add_command("status", "Reports.", works);
+        h.add_command("start", "Starts.", works);
+        assert_eq!(feed(&mut h, "stauts\n"), Some(Err(unknown("stauts", &["status", "start"]))));
+        assert_eq!(feed(&mut h, "hepl\n"), Some(Err(unknown("hepl", &["help"]))));
+        assert_eq!(feed(&mut h, "net ip sete\n"), Some(Err(unknown_sub("sete", &["set"]))));
+        h.set_suggestion_distance(1);
+        assert_eq!(feed(&mut h, "stauts\n"), Some(Err(unknown("stauts", &["status"]))));
+        h.set_suggestion_distance(0);
+        assert_eq!(feed(&mut h, "stauts\n"), Some(Err(unknown("stauts", &[]))));
+        h.set_suggestion_distance(2);
+        h.writer_mut().clear();
+        for b in "stauts\n".bytes() {
+            h.receive_and_print(b).unwrap();
         }
+        assert_eq!(h.writer(),
+                   "Error: unknown command 'stauts', did you mean 'status' or 'start'?\n> ");
     }
 
-    pub fn receive(&mut self, c: u8) -> Option<Result<(), &'static str>> {
-        if c == '\n' as u8 {
-            Some(self.process())
-        } else {
-            self.cmdline.push(c);
-            None
+    static RENAMED: [Command; 2] = [Command::builtin("?", Builtin::Help),
+                                    Command::new("foo", "Does stuff.", works)];
+
+    #[test]
+    fn builtins() {
+        let mut h = super::Harness::with_commands(String::new(), &RENAMED);
+        h.set_builtins(&[Builtin::Echo, Builtin::Clear, Builtin::Version, Builtin::Exit]);
+        h.set_version("1.2.3");
+        assert_eq!(feed(&mut h, "help\n"), Some(Err(unknown("help", &[]))));
+        assert_eq!(feed(&mut h, "echo  a \"b c\"\nclear\nversion\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "a b c\n\x1b[2J\x1b[H1.2.3\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "?\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(),
+                   "  ?        Lists the commands, or describes one\n  foo      Does stuff.\n  echo     Prints \
+                    its arguments\n  clear    Clears the screen\n  version  Prints the version\n  exit     \
+                    Ends the session\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "? echo\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "Command: echo - Prints its arguments\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "? f\t"), None);
+        assert_eq!(h.writer(), "oo ");
+        assert_eq!(feed(&mut h, "\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "exit\n"), Some(Ok(Outcome::EndSession)));
+
+        // A registered command hides a built-in of the same name
+        h.add_command("echo", "Echoes.", fails);
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "echo\n"), Some(Err("boom".into())));
+        h.add_builtin("say", Builtin::Echo);
+        assert_eq!(feed(&mut h, "say hi\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "Fails!\nhi\n");
+    }
+
+    fn restart(_: &mut (), _args: &Args, _out: &mut dyn Write) -> Result<Outcome, Error> {
+        Ok(Outcome::RestartSession)
+    }
+
+    static SESSION: [Command; 2] = [Command::with_outcome("restart", "Starts again.", restart),
+                                    Command::new("foo", "Does stuff.", works)];
+
+    #[test]
+    fn outcomes() {
+        let mut h = super::Harness::with_commands(String::new(), &SESSION);
+        h.set_builtins(&[Builtin::Exit]);
+        h.add_outcome_command("bye", "Says goodbye.", |_: &mut (), _: &Args, out: &mut dyn Write| {
+            writeln!(out, "Bye!")?;
+            Ok(Outcome::EndSession)
+        });
+        assert_eq!(feed(&mut h, "restart\n"), Some(Ok(Outcome::RestartSession)));
+        assert_eq!(feed(&mut h, "bye\n"), Some(Ok(Outcome::EndSession)));
+        h.writer_mut().clear();
+        let mut outcomes = Vec::new();
+        for b in "foo\nexit\n".bytes() {
+            outcomes.push(h.receive_and_print(b).unwrap());
         }
+        assert_eq!(outcomes.iter().filter(|o| **o == Outcome::Continue).count(), 8);
+        assert_eq!(outcomes.last(), Some(&Outcome::EndSession));
+        assert_eq!(h.writer(), "Works!\n> ");
     }
 
-    pub fn process(&mut self) -> Result<(), &'static str> {
-        let mut cmd_name = Vec::new();
-        std::mem::swap(&mut self.cmdline, &mut cmd_name);
-        match std::str::from_utf8(&cmd_name) {
-            Ok("help") => self.print_help().and(Ok(())).or(Err("I/O error printing help")),
-            Ok(s) => {
-                if let Some(cmd) = self.commands.get(&s) {
-                    ((*cmd).handler)()
-                } else {
-                    Err("Invalid command")
-                }
-            }
-            Err(_) => Err("Command is invalid UTF-8"),
+    #[test]
+    fn scripts() {
+        use super::{OnError, ScriptError};
+
+        let mut h = super::Harness::new(String::new());
+        h.add_command("foo", "Does stuff.", works);
+        h.add_command("bar", "Doesn't.", fails);
+        let script = "foo\n  # A comment\n\nbar\nxyzzy\nfoo\n";
+        assert_eq!(h.run_script(script, OnError::Stop),
+                   Err(ScriptError { line: 4, error: "boom".into() }));
+        assert_eq!(h.writer(), "Works!\nFails!\n");
+        h.writer_mut().clear();
+        assert_eq!(h.run_script(script, OnError::Continue),
+                   Err(ScriptError { line: 4, error: "boom".into() }));
+        assert_eq!(h.writer(),
+                   "Works!\nFails!\nError: line 4: boom\nError: line 5: unknown command 'xyzzy'\nWorks!\n");
+        h.writer_mut().clear();
+        assert_eq!(h.run_script_from(&b"foo\r\n\"unterminated\r\nfoo"[..], OnError::Continue),
+                   Err(ScriptError { line: 2, error: Error::UnterminatedQuote }));
+        assert_eq!(h.writer(), "Works!\nError: line 2: unterminated quote\nWorks!\n");
+        assert_eq!(h.run_script("foo\n", OnError::Stop), Ok(Outcome::Continue));
+        assert_eq!(h.history.len(), 0);
+
+        h.set_builtins(&[Builtin::Exit]);
+        h.writer_mut().clear();
+        assert_eq!(h.run_script("foo\nexit\nfoo\n", OnError::Stop), Ok(Outcome::EndSession));
+        assert_eq!(h.writer(), "Works!\n");
+    }
+
+    #[test]
+    fn chaining_quietly() {
+        let mut h = super::Harness::new(String::new());
+        h.add_command("led", "Controls an LED.", led);
+        for b in "led x; led 3 on".bytes() {
+            assert_eq!(h.receive(b), None);
         }
+        assert_eq!(h.process(), Ok(Outcome::Continue));
+        assert_eq!(h.writer(), "");
     }
-}
 
-#[cfg(test)]
-mod tests {
-    fn works() -> Result<(), &'static str> {
-        println!("Works!");
+    #[test]
+    fn chaining() {
+        use super::{OnError, ScriptError};
+
+        let mut h = super::Harness::new(String::new());
+        h.add_command("foo", "Does stuff.", works);
+        h.add_command("bar", "Doesn't.", fails);
+        h.set_builtins(&[Builtin::Echo, Builtin::Exit]);
+        assert_eq!(feed(&mut h, "foo; bar ;foo\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "Works!\nFails!\nWorks!\n");
+        h.writer_mut().clear();
+        // Only `receive_and_print` prints the errors that a later command
+        // supersedes
+        for b in "foo; bar ;foo\n".bytes() {
+            assert_eq!(h.receive_and_print(b), Ok(Outcome::Continue));
+        }
+        assert_eq!(h.writer(), "Works!\nFails!\nError: boom\nWorks!\n> ");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "bar && foo\n"), Some(Err("boom".into())));
+        assert_eq!(h.writer(), "Fails!\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "bar && foo || echo recovered\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "Fails!\nrecovered\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "foo || bar && echo 'a; b && c'\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "Works!\na; b && c\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "foo; exit; foo\n"), Some(Ok(Outcome::EndSession)));
+        assert_eq!(h.writer(), "Works!\n");
+        assert_eq!(h.history.len(), 5);
+        h.writer_mut().clear();
+        let missing = |before, operator| Some(Err(Error::MissingCommand { before, operator }));
+        assert_eq!(feed(&mut h, "&& foo\n"), missing(true, "&&"));
+        assert_eq!(feed(&mut h, "foo ||\n"), missing(false, "||"));
+        assert_eq!(feed(&mut h, ";;foo;;\n"), missing(true, ";"));
+        assert_eq!(h.writer(), "");
+        assert_eq!(feed(&mut h, "foo;\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "Works!\n");
+        h.writer_mut().clear();
+        assert_eq!(h.run_script("foo && bar\nbar || foo\n", OnError::Stop),
+                   Err(ScriptError { line: 1, error: "boom".into() }));
+        assert_eq!(h.writer(), "Works!\nFails!\n");
+    }
+
+    #[test]
+    fn variables() {
+        use super::OnError;
+
+        let mut h = super::Harness::new(String::new());
+        h.add_command("bar", "Doesn't.", fails);
+        h.set_builtins(&[Builtin::Echo, Builtin::Set, Builtin::Unset, Builtin::Env]);
+        assert_eq!(feed(&mut h, "set addr 0x4000; set count 3\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.variable("addr"), Some("0x4000"));
+        assert_eq!(feed(&mut h, "echo peek ${addr} $count; env\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "peek 0x4000 3\naddr=0x4000\ncount=3\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "bar; echo $?; echo $?\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "Fails!\n1\n0\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "unset addr; echo [$addr]\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "[]\n");
+        assert!(!h.unset_variable("addr"));
+        assert_eq!(feed(&mut h, "set 2x y\n"), Some(Err(Error::failed("'2x' isn't a valid variable name"))));
+        h.set_variable("greeting", "hello world").unwrap();
+        h.writer_mut().clear();
+        assert_eq!(h.run_script("set n $greeting\necho $n\n", OnError::Stop), Ok(Outcome::Continue));
+        assert_eq!(h.writer(), "hello world\n");
+        assert_eq!(h.history.get(0), Some(&b"set 2x y"[..]));
+    }
+
+    #[test]
+    fn macros() {
+        let mut h = super::Harness::new(String::new());
+        h.add_command("foo", "Does stuff.", works);
+        h.add_command("bar", "Doesn't.", fails);
+        h.set_builtins(&[Builtin::Help, Builtin::Echo, Builtin::Alias]);
+        assert_eq!(feed(&mut h, "alias both = \"foo; bar\"\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "both\n"), Some(Err("boom".into())));
+        assert_eq!(h.writer(), "Works!\nFails!\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "alias say = \"echo [$2] [$1]\"; say a \"b c\"\n"),
+                   Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "[b c] [a]\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "alias\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "both = foo; bar\nsay = echo [$2] [$1]\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "alias say\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "say = echo [$2] [$1]\n");
+        assert_eq!(feed(&mut h, "alias nope\n"), Some(Err(Error::failed("no macro called nope"))));
+        assert_eq!(feed(&mut h, "alias x y\n"),
+                   Some(Err(Error::failed("expected alias <name> = <commands>"))));
+
+        // A macro hides a command, but can't run itself
+        h.define_macro("foo", "echo hidden; foo").unwrap();
+        assert_eq!(feed(&mut h, "foo\n"), Some(Err(Error::RecursiveMacro("foo".into()))));
+        h.define_macro("ping", "pong").unwrap();
+        h.define_macro("pong", "ping").unwrap();
+        assert_eq!(feed(&mut h, "ping\n"), Some(Err(Error::RecursiveMacro("ping".into()))));
+        assert_eq!(feed(&mut h, "pnig\n"), Some(Err(unknown("pnig", &["ping", "pong"]))));
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "help both\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "Macro: both = foo; bar\n");
+        h.writer_mut().clear();
+        h.print_help().unwrap();
+        assert!(h.writer().ends_with("\nMacros:\n  both   foo; bar\n  foo    echo hidden; foo\n  \
+                                      ping   pong\n  pong   ping\n  say    echo [$2] [$1]\n"));
+    }
+
+    #[test]
+    fn macro_too_long() {
+        let mut h: super::Harness<String, (), 16> = super::Harness::sized(String::new(), &[]);
+        h.add_command("foo", "Does stuff.", works);
+        assert_eq!(h.define_macro("long", "foo; foo; foo; foo"),
+                   Err(Error::failed("too long for a command line, which holds at most 16 bytes")));
+        assert_eq!(h.macros.get("long"), None);
+        h.define_macro("three", "foo; foo;   foo").unwrap();
+        for b in "three\n".bytes() {
+            h.receive(b);
+        }
+        assert_eq!(h.writer(), "Works!\nWorks!\nWorks!\n");
+    }
+
+    fn words(seen: &mut Vec<String>, args: &Args, _out: &mut dyn Write) -> CommandResult {
+        seen.push(format!("{:?}", &args[..]));
         Ok(())
     }
 
-    fn fails() -> Result<(), &'static str> {
-        println!("Fails!");
-        Err("boom")
+    #[test]
+    fn macro_bodies() {
+        let mut seen = Vec::new();
+        let mut h = super::Harness::new(String::new());
+        h.add_context_command("show", "Shows its arguments.", words);
+        h.set_builtins(&[Builtin::Alias, Builtin::Set]);
+        let lines = "alias f = show 3 'with spaces'\nf\nalias g = show 'a;b'\ng\n\
+                     alias b = \"show $1 on\"\nset x 1\nalias v = show $x\nset x 2\nb led; v\nalias\n";
+        for b in lines.bytes() {
+            h.receive_with(&mut seen, b);
+        }
+        assert_eq!(seen, [r#"["3", "with spaces"]"#, r#"["a;b"]"#, r#"["led", "on"]"#, r#"["2"]"#]);
+        assert!(h.writer()
+                 .ends_with("b = show $1 on\nf = show 3 'with spaces'\ng = show 'a;b'\nv = show $x\n"));
     }
 
     #[test]
-    fn bad_command() {
+    fn repeat() {
+        let mut h = super::Harness::new(String::new());
+        h.add_command("foo", "Does stuff.", works);
+        h.add_command("bar", "Doesn't.", fails);
+        h.set_builtins(&[Builtin::Help, Builtin::Exit, Builtin::Repeat]);
+        assert_eq!(feed(&mut h, "repeat 3 foo\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "Works!\nWorks!\nWorks!\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "repeat 3 bar; repeat 0 foo\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "Fails!\n");
+        assert_eq!(feed(&mut h, "repeat 2 exit\n"), Some(Ok(Outcome::EndSession)));
+        assert_eq!(feed(&mut h, "repeat 2\n"),
+                   Some(Err(Error::InvalidArgument {
+                       problem: "arg 2 (command): missing".into(),
+                       usage: "Usage: repeat <count> <command>".into(),
+                   })));
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "help repeat\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(),
+                   "Command: repeat - Runs a command a number of times\nUsage: repeat <count> <command>\n\
+                    Arguments:\n  count    How many times to run the command (0..=1000)\n  command  The \
+                    command to run, and its arguments\n");
+        for count in ["-5", "1001"] {
+            let problem = format!("arg 1 (count): {} out of range 0..=1000", count);
+            assert_eq!(feed(&mut h, &format!("repeat {} foo\n", count)),
+                       Some(Err(Error::InvalidArgument {
+                           problem: problem.as_str().into(),
+                           usage: "Usage: repeat <count> <command>".into(),
+                       })));
+        }
+        assert!(matches!(feed(&mut h, "repeat lots foo\n"), Some(Err(Error::InvalidArgument { .. }))));
+    }
 
-        let outbuf: Vec<u8> = Vec::new();
-        let mut h = super::Harness::new(outbuf);
-        h.add_command("foobar", "test function", works);
-        assert_eq!(h.receive('h' as u8), None);
-        assert_eq!(h.receive('h' as u8), None);
-        assert_eq!(h.receive('h' as u8), None);
-        assert_eq!(h.receive('h' as u8), None);
-        assert_eq!(h.receive('\n' as u8), Some(Err("Invalid command")));
+    /// A clock whose time the test sets.
+    struct TestClock(core::cell::Cell<u64>);
+
+    impl super::Clock for TestClock {
+        fn now_ms(&self) -> u64 {
+            self.0.get()
+        }
     }
 
     #[test]
-    fn good_command() {
-        let outbuf: Vec<u8> = Vec::new();
-        let mut h = super::Harness::new(outbuf);
+    fn watch() {
+        use super::CLEAR_SCREEN;
+
+        let clock = TestClock(core::cell::Cell::new(1000));
+        let mut h = super::Harness::new(String::new());
         h.add_command("foo", "Does stuff.", works);
-        assert_eq!(h.receive('f' as u8), None);
-        assert_eq!(h.receive('o' as u8), None);
-        assert_eq!(h.receive('o' as u8), None);
-        assert_eq!(h.receive('\n' as u8), Some(Ok(())));
+        h.add_command("bar", "Doesn't.", fails);
+        h.set_builtins(&[Builtin::Watch]);
+        assert_eq!(feed(&mut h, "watch 1 foo\n"), Some(Err(Error::failed("watch needs a clock"))));
+        h.set_clock(&clock);
+        for seconds in ["-3", "0"] {
+            let problem = format!("arg 1 (seconds): {} out of range 0.1..=3600", seconds);
+            assert_eq!(feed(&mut h, &format!("watch {} foo\n", seconds)),
+                       Some(Err(Error::InvalidArgument {
+                           problem: problem.as_str().into(),
+                           usage: "Usage: watch <seconds> <command>".into(),
+                       })));
+            assert!(!h.is_watching());
+        }
+        h.writer_mut().clear();
+        for b in "watch 0.5 foo\n".bytes() {
+            assert_eq!(h.receive_and_print(b), Ok(Outcome::Continue));
+        }
+        let screen = format!("{}Every 0.5s: foo\nWorks!\n", CLEAR_SCREEN);
+        assert_eq!(h.writer(), &screen);
+        assert!(h.is_watching());
+        assert_eq!(h.poll(), None);
+        clock.0.set(1499);
+        assert_eq!(h.poll(), None);
+        clock.0.set(1500);
+        assert_eq!(h.poll(), Some(Outcome::Continue));
+        assert_eq!(h.writer(), &screen.repeat(2));
+
+        // The up arrow key stops the watch, rather than recalling a line
+        h.writer_mut().clear();
+        for b in "\x1b[".bytes() {
+            assert_eq!(h.receive_and_print(b), Ok(Outcome::Continue));
+            assert!(h.is_watching());
+        }
+        assert_eq!(h.receive_and_print(b'A'), Ok(Outcome::Continue));
+        assert!(!h.is_watching());
+        assert_eq!(h.writer(), "> ");
+        assert_eq!(h.line.as_bytes(), b"");
+        clock.0.set(5000);
+        assert_eq!(h.poll(), None);
+
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "watch 1 bar\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), &format!("{}Every 1s: bar\nFails!\nError: boom\n", CLEAR_SCREEN));
+        assert_eq!(h.receive(b'\r'), Some(Ok(Outcome::Continue)));
+        assert!(!h.is_watching());
+        assert_eq!(h.receive(b'\n'), None);
+        assert_eq!(h.history.len(), 5);
     }
 
     #[test]
-    fn good_command_but_fails() {
-        let outbuf: Vec<u8> = Vec::new();
-        let mut h = super::Harness::new(outbuf);
-        h.add_command("foo", "Does stuff.", fails);
-        assert_eq!(h.receive('f' as u8), None);
-        assert_eq!(h.receive('o' as u8), None);
-        assert_eq!(h.receive('o' as u8), None);
-        assert_eq!(h.receive('\n' as u8), Some(Err("boom")));
+    fn watch_alone() {
+        static SCRIPTS: [(&str, &str); 1] = [("boot", "watch 1 foo\necho after\n")];
+        let clock = TestClock(core::cell::Cell::new(0));
+        let mut h = super::Harness::new(String::new());
+        h.add_command("foo", "Does stuff.", works);
+        h.set_builtins(&[Builtin::Echo, Builtin::Watch, Builtin::Repeat, Builtin::Source, Builtin::Alias]);
+        h.set_scripts(&SCRIPTS);
+        h.set_clock(&clock);
+        const ALONE: &str = "watch must be the only command on a line typed at the prompt";
+        let alone = || Some(Err(Error::failed(ALONE)));
+        assert_eq!(feed(&mut h, "watch 1 foo && echo after\n"), alone());
+        assert_eq!(feed(&mut h, "watch 1 foo; echo after\n"), Some(Ok(Outcome::Continue)));
+        assert!(!h.is_watching());
+        assert_eq!(h.writer(), "after\n");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "echo before && watch 1 foo\n"), alone());
+        assert_eq!(feed(&mut h, "repeat 3 watch 1 foo\n"), alone());
+        let error = Box::new(alone().unwrap().unwrap_err());
+        assert_eq!(feed(&mut h, "source boot\n"),
+                   Some(Err(Error::Script { name: "boot".into(), line: 1, error })));
+        assert_eq!(feed(&mut h, "alias w = \"echo before; watch 1 foo\"\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, "w\n"), alone());
+        assert_eq!(feed(&mut h, "watch 1 watch 1 foo\n"), Some(Ok(Outcome::Continue)));
+        assert!(h.writer().ends_with(&format!("Every 1s: watch 1 foo\nError: {}\n", ALONE)));
+        assert_eq!(h.receive(b'\r'), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer().matches("after").count(), 0);
+        assert_eq!(h.writer().matches(super::CLEAR_SCREEN).count(), 1);
+        // On its own, even in a macro, it can
+        assert_eq!(feed(&mut h, "alias v = watch 1 foo\nv\n"), Some(Ok(Outcome::Continue)));
+        assert!(h.is_watching());
     }
 
     #[test]
-    fn good_command_twice() {
-        let outbuf: Vec<u8> = Vec::new();
-        let mut h = super::Harness::new(outbuf);
+    fn source() {
+        static SCRIPTS: [(&str, &str); 4] = [("boot", "foo\nsource init\n"),
+                                             ("init", "# Nothing to see here\nfoo\n"),
+                                             ("broken", "foo\nsource bad\n"),
+                                             ("loop", "source loop\n")];
+        static NESTED: [(&str, &str); 2] = [("outer", "foo\nsource inner\n"), ("inner", "foo\nxyzzy\n")];
+        let mut h = super::Harness::new(String::new());
         h.add_command("foo", "Does stuff.", works);
-        assert_eq!(h.receive('f' as u8), None);
-        assert_eq!(h.receive('o' as u8), None);
-        assert_eq!(h.receive('o' as u8), None);
-        assert_eq!(h.receive('\n' as u8), Some(Ok(())));
-        assert_eq!(h.receive('f' as u8), None);
-        assert_eq!(h.receive('o' as u8), None);
-        assert_eq!(h.receive('o' as u8), None);
-        assert_eq!(h.receive('\n' as u8), Some(Ok(())));
+        h.set_builtins(&[Builtin::Help, Builtin::Source]);
+        h.set_scripts(&SCRIPTS);
+        assert_eq!(feed(&mut h, "source boot\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(), "Works!\nWorks!\n");
+        let missing = Error::failed("no script called bad");
+        assert_eq!(feed(&mut h, "source broken\n"),
+                   Some(Err(Error::Script { name: "broken".into(), line: 2, error: Box::new(missing) })));
+        assert_eq!(feed(&mut h, "source loop\n"), Some(Err(Error::NestedTooDeep)));
+        assert_eq!(feed(&mut h, "source\n"),
+                   Some(Err(Error::InvalidArgument {
+                       problem: "arg 1 (script): missing".into(),
+                       usage: "Usage: source <script>".into(),
+                   })));
+        // The built-in's own parameters are checked, whatever it is given
+        h.add_builtin("src", Builtin::Source).params(&[]);
+        assert_eq!(feed(&mut h, "src\n"),
+                   Some(Err(Error::InvalidArgument {
+                       problem: "arg 1 (script): missing".into(),
+                       usage: "Usage: src <script>".into(),
+                   })));
+        h.add_builtin("put", Builtin::Set).params(&[]);
+        assert!(matches!(feed(&mut h, "put x\n"), Some(Err(Error::InvalidArgument { .. }))));
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "help src\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(),
+                   "Command: src - Runs a script\nUsage: src <script>\nArguments:\n  script  The name of the \
+                    script\n");
+        h.set_scripts(&NESTED);
+        let error = match feed(&mut h, "source outer\n") {
+            Some(Err(Error::Script { name, line: 2, error })) if name.as_str() == "outer" => *error,
+            other => panic!("{:?}", other),
+        };
+        assert!(matches!(error, Error::Script { line: 2, ref error, .. }
+                                    if matches!(**error, Error::UnknownCommand { .. })));
+        assert_eq!(error.to_string(), "inner line 2: unknown command 'xyzzy'");
+        h.writer_mut().clear();
+        assert_eq!(feed(&mut h, "help source\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer(),
+                   "Command: source - Runs a script\nUsage: source <script>\nArguments:\n  script  The name \
+                    of the script\n");
+    }
+
+    fn unknown(name: &str, suggestions: &[&str]) -> Error {
+        Error::UnknownCommand {
+            name: name.into(),
+            suggestions: suggestions.iter().copied().collect(),
+        }
+    }
+
+    fn unknown_sub(name: &str, suggestions: &[&str]) -> Error {
+        Error::UnknownSubcommand {
+            name: name.into(),
+            suggestions: suggestions.iter().copied().collect(),
+        }
+    }
+
+    /// A writer whose output has gone away.
+    struct Broken;
+
+    impl Write for Broken {
+        fn write_str(&mut self, _s: &str) -> core::fmt::Result {
+            Err(core::fmt::Error)
+        }
     }
 
     #[test]
-    fn help() {
-        let outbuf: Vec<u8> = Vec::new();
-        let mut h = super::Harness::new(outbuf);
-        assert_eq!(h.receive('h' as u8), None);
-        assert_eq!(h.receive('e' as u8), None);
-        assert_eq!(h.receive('l' as u8), None);
-        assert_eq!(h.receive('p' as u8), None);
-        assert_eq!(h.receive('\n' as u8), Some(Ok(())));
+    fn io_error() {
+        let mut h = super::Harness::new(Broken);
+        h.add_command("foo", "Does stuff.", works);
+        assert_eq!(feed(&mut h, "help\n"),
+                   Some(Err(Error::Io { context: "printing help", source: core::fmt::Error })));
+        assert_eq!(feed(&mut h, "help foo\n"),
+                   Some(Err(Error::Io { context: "printing help", source: core::fmt::Error })));
+    }
+}
+
+#[cfg(all(test, not(feature = "std")))]
+mod no_std_tests {
+    use super::{Args, Builtin, Command, CommandResult, Error, Harness, Outcome, Param};
+    use core::fmt::{self, Write};
+
+    /// A fixed-size output buffer, as a board might print to.
+    struct Sink {
+        buf: [u8; 256],
+        len: usize,
+    }
+
+    impl Sink {
+        fn new() -> Sink {
+            Sink { buf: [0; 256], len: 0 }
+        }
+
+        fn as_str(&self) -> &str {
+            core::str::from_utf8(&self.buf[..self.len]).unwrap()
+        }
+    }
+
+    impl Write for Sink {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            self.buf.get_mut(self.len..end).ok_or(fmt::Error)?.copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    fn led(args: &Args, out: &mut dyn Write) -> CommandResult {
+        writeln!(out, "LED {} {}", args.int("index").unwrap(), args.get("state").unwrap())?;
+        Ok(())
+    }
+
+    fn show(_args: &Args, out: &mut dyn Write) -> CommandResult {
+        writeln!(out, "up")?;
+        Ok(())
+    }
+
+    fn count(total: &mut u32, _args: &Args, _out: &mut dyn Write) -> CommandResult {
+        *total += 1;
+        Ok(())
+    }
+
+    static LED_PARAMS: [Param; 2] = [Param::int_range("index", 0, 7), Param::choice("state", &["on", "off"])];
+    static IP: [Command; 1] = [Command::new("show", "Shows the address", show)];
+    static TABLE: [Command; 3] = [Command::new("led", "Sets an LED", led).with_params(&LED_PARAMS),
+                                  Command::group("net", "Networking", &IP),
+                                  Command::new("status", "Shows the status", show)];
+    static COUNTED: [Command<u32>; 1] = [Command::with_context("count", "Counts", count)];
+
+    fn feed<T>(h: &mut Harness<Sink, T>, context: &mut T, line: &str) -> Option<Result<Outcome, Error>> {
+        let mut result = None;
+        for b in line.bytes() {
+            result = h.receive_with(context, b);
+        }
+        result
+    }
+
+    #[test]
+    fn dispatch() {
+        let mut h = Harness::with_commands(Sink::new(), &TABLE);
+        assert_eq!(feed(&mut h, &mut (), "led 3 on\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(feed(&mut h, &mut (), "net show\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer().as_str(), "LED 3 on\nup\n");
+        assert!(matches!(feed(&mut h, &mut (), "led 9 on\n"), Some(Err(Error::InvalidArgument { .. }))));
+        match feed(&mut h, &mut (), "stauts\n") {
+            Some(Err(Error::UnknownCommand { name, suggestions })) => {
+                assert_eq!(name.as_str(), "stauts");
+                assert!(suggestions.iter().eq(["status"]));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(feed(&mut h, &mut (), "net ip\n"), Some(Err(Error::UnknownSubcommand { .. }))));
+    }
+
+    #[test]
+    fn groups() {
+        let mut h = Harness::with_commands(Sink::new(), &TABLE);
+        h.set_builtins(&[Builtin::Help]);
+        assert_eq!(feed(&mut h, &mut (), "net\n"), Some(Ok(Outcome::Continue)));
+        assert_eq!(h.writer().as_str(), "  show  Shows the address\n");
+        h.writer_mut().len = 0;
+        h.print_help().unwrap();
+        assert_eq!(h.writer().as_str(),
+                   "  led     Sets an LED\n  net     Networking\n  status  Shows the status\n  help    Lists \
+                    the commands, or describes one\n");
+    }
+
+    #[test]
+    fn scripts() {
+        static SCRIPTS: [(&str, &str); 1] = [("boot", "status\nled 9 on\n")];
+        let mut h = Harness::with_commands(Sink::new(), &TABLE);
+        h.set_builtins(&[Builtin::Source]);
+        h.set_scripts(&SCRIPTS);
+        // The error is kept as it is, as there is no room for where it happened
+        match feed(&mut h, &mut (), "source boot\n") {
+            Some(Err(Error::InvalidArgument { problem, .. })) => {
+                assert_eq!(problem.as_str(), "arg 1 (index): 9 out of range 0..=7");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(h.writer().as_str(), "up\n");
+    }
+
+    #[test]
+    fn context() {
+        let mut h: Harness<Sink, u32> = Harness::with_commands(Sink::new(), &COUNTED);
+        let mut total = 0;
+        feed(&mut h, &mut total, "count\n");
+        feed(&mut h, &mut total, "count\n");
+        assert_eq!(total, 2);
     }
 }
@@ -0,0 +1,311 @@
+/// A fixed-capacity buffer holding the command line as it is typed, and the
+/// position of the cursor within it.
+pub struct Line<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    /// Byte offset of the cursor. Always on a character boundary.
+    cursor: usize,
+    /// How many bytes have been typed but not stored, because the buffer was
+    /// full.
+    dropped: usize,
+}
+
+fn is_continuation(b: u8) -> bool {
+    (b & 0xC0) == 0x80
+}
+
+fn char_count(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|b| !is_continuation(**b)).count()
+}
+
+impl<const N: usize> Line<N> {
+    pub const fn new() -> Line<N> {
+        Line {
+            buf: [0u8; N],
+            len: 0,
+            cursor: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Inserts a byte at the cursor. If the buffer is full the byte is
+    /// dropped, the line is marked as overflowed, and false is returned.
+    pub fn insert(&mut self, b: u8) -> bool {
+        if self.len == N {
+            self.dropped += 1;
+            return false;
+        }
+        self.buf.copy_within(self.cursor..self.len, self.cursor + 1);
+        self.buf[self.cursor] = b;
+        self.len += 1;
+        self.cursor += 1;
+        true
+    }
+
+    /// Replaces the contents of the line, leaving the cursor at the end.
+    pub fn set(&mut self, bytes: &[u8]) {
+        self.clear();
+        for b in bytes {
+            self.insert(*b);
+        }
+    }
+
+    /// Where the character before the cursor starts.
+    fn prev(&self) -> usize {
+        let mut pos = self.cursor.saturating_sub(1);
+        while pos > 0 && is_continuation(self.buf[pos]) {
+            pos -= 1;
+        }
+        pos
+    }
+
+    /// Where the character after the cursor ends.
+    fn next(&self) -> usize {
+        let mut pos = (self.cursor + 1).min(self.len);
+        while pos < self.len && is_continuation(self.buf[pos]) {
+            pos += 1;
+        }
+        pos
+    }
+
+    /// Removes the bytes from `start` up to the cursor, leaving the cursor at
+    /// `start`.
+    fn remove_before(&mut self, start: usize) -> usize {
+        let count = char_count(&self.buf[start..self.cursor]);
+        self.buf.copy_within(self.cursor..self.len, start);
+        self.len -= self.cursor - start;
+        self.cursor = start;
+        count
+    }
+
+    /// Removes the character before the cursor. Returns false if there was
+    /// nothing to remove.
+    pub fn backspace(&mut self) -> bool {
+        if self.dropped > 0 && self.cursor == self.len {
+            self.dropped -= 1;
+            return true;
+        }
+        if self.cursor == 0 {
+            return false;
+        }
+        let start = self.prev();
+        self.remove_before(start);
+        true
+    }
+
+    /// Removes the character under the cursor. Returns false if there was
+    /// nothing to remove.
+    pub fn delete(&mut self) -> bool {
+        if self.cursor == self.len {
+            return false;
+        }
+        let end = self.next();
+        self.buf.copy_within(end..self.len, self.cursor);
+        self.len -= end - self.cursor;
+        true
+    }
+
+    /// Removes the word before the cursor, and any whitespace between it and
+    /// the cursor. Returns how many characters were removed.
+    pub fn erase_word(&mut self) -> usize {
+        if self.cursor == self.len && self.dropped > 0 {
+            // We can't tell what was dropped, so just forget about it
+            let count = self.dropped;
+            self.dropped = 0;
+            return count;
+        }
+        let mut start = self.cursor;
+        while start > 0 && self.buf[start - 1] == b' ' {
+            start -= 1;
+        }
+        while start > 0 && self.buf[start - 1] != b' ' {
+            start -= 1;
+        }
+        self.remove_before(start)
+    }
+
+    /// Removes everything before the cursor. Returns how many characters were
+    /// removed.
+    pub fn erase_to_start(&mut self) -> usize {
+        let dropped = if self.cursor == self.len { core::mem::replace(&mut self.dropped, 0) } else { 0 };
+        dropped + self.remove_before(0)
+    }
+
+    /// Moves the cursor one character left. Returns false if it was already
+    /// at the start.
+    pub fn left(&mut self) -> bool {
+        if self.cursor == 0 {
+            return false;
+        }
+        self.cursor = self.prev();
+        true
+    }
+
+    /// Moves the cursor one character right, returning the character moved
+    /// over.
+    pub fn right(&mut self) -> Option<&str> {
+        if self.cursor == self.len {
+            return None;
+        }
+        let start = self.cursor;
+        self.cursor = self.next();
+        core::str::from_utf8(&self.buf[start..self.cursor]).ok()
+    }
+
+    /// Moves the cursor to the start of the line, returning how many
+    /// characters it moved over.
+    pub fn home(&mut self) -> usize {
+        let count = char_count(self.before());
+        self.cursor = 0;
+        count
+    }
+
+    /// Moves the cursor to the end of the line.
+    pub fn end(&mut self) {
+        self.cursor = self.len;
+    }
+
+    /// If the last byte inserted completed a character, returns that
+    /// character.
+    pub fn last_char(&self) -> Option<&str> {
+        let before = self.before();
+        let start = before.iter().rposition(|b| !is_continuation(*b))?;
+        core::str::from_utf8(&before[start..]).ok()
+    }
+
+    /// How many characters are on the line.
+    pub fn chars(&self) -> usize {
+        self.dropped + char_count(self.as_bytes())
+    }
+
+    /// The bytes before the cursor.
+    pub fn before(&self) -> &[u8] {
+        &self.buf[..self.cursor]
+    }
+
+    /// The text from the cursor to the end of the line, if it is valid UTF-8.
+    pub fn after(&self) -> &str {
+        core::str::from_utf8(&self.buf[self.cursor..self.len]).unwrap_or_default()
+    }
+
+    /// Have any bytes been dropped since the line was last cleared?
+    pub fn overflowed(&self) -> bool {
+        self.dropped > 0
+    }
+
+    /// How many more bytes the line can hold.
+    pub fn room(&self) -> usize {
+        N - self.len
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.cursor = 0;
+        self.dropped = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Line;
+
+    fn line<const N: usize>(s: &str) -> Line<N> {
+        let mut line = Line::new();
+        line.set(s.as_bytes());
+        line
+    }
+
+    #[test]
+    fn overflow() {
+        let mut line: Line<4> = line("abcd");
+        assert!(!line.overflowed());
+        assert!(!line.insert(b'e'));
+        assert!(line.overflowed());
+        assert_eq!(line.as_bytes(), b"abcd");
+        line.clear();
+        assert!(!line.overflowed());
+        assert_eq!(line.as_bytes(), b"");
+    }
+
+    #[test]
+    fn backspace() {
+        let mut line: Line<8> = line("ab\u{e9}");
+        assert_eq!(line.chars(), 3);
+        assert!(line.backspace());
+        assert_eq!(line.as_bytes(), b"ab");
+        assert!(line.backspace());
+        assert!(line.backspace());
+        assert!(!line.backspace());
+    }
+
+    #[test]
+    fn backspace_overflowed() {
+        let mut line: Line<2> = line("abc");
+        assert!(line.overflowed());
+        assert!(line.backspace());
+        assert!(!line.overflowed());
+        assert_eq!(line.as_bytes(), b"ab");
+    }
+
+    #[test]
+    fn erase_word() {
+        let mut line: Line<32> = line("led 3 on  ");
+        assert_eq!(line.erase_word(), 4);
+        assert_eq!(line.as_bytes(), b"led 3 ");
+        assert_eq!(line.erase_word(), 2);
+        assert_eq!(line.erase_word(), 4);
+        assert_eq!(line.erase_word(), 0);
+    }
+
+    #[test]
+    fn last_char() {
+        let mut line: Line<8> = Line::new();
+        line.insert(b'a');
+        assert_eq!(line.last_char(), Some("a"));
+        line.insert(0xC3);
+        assert_eq!(line.last_char(), None);
+        line.insert(0xA9);
+        assert_eq!(line.last_char(), Some("\u{e9}"));
+    }
+
+    #[test]
+    fn movement() {
+        let mut line: Line<16> = line("a\u{e9}c");
+        assert!(line.left());
+        assert_eq!(line.after(), "c");
+        assert!(line.left());
+        assert_eq!(line.after(), "\u{e9}c");
+        assert_eq!(line.home(), 1);
+        assert!(!line.left());
+        assert_eq!(line.right(), Some("a"));
+        assert_eq!(line.right(), Some("\u{e9}"));
+        line.end();
+        assert_eq!(line.right(), None);
+        assert_eq!(line.after(), "");
+    }
+
+    #[test]
+    fn edit_in_middle() {
+        let mut line: Line<16> = line("led on");
+        line.left();
+        line.left();
+        assert!(line.insert(b'3'));
+        assert!(line.insert(b' '));
+        assert_eq!(line.as_bytes(), b"led 3 on");
+        assert_eq!(line.after(), "on");
+        assert!(line.delete());
+        assert_eq!(line.as_bytes(), b"led 3 n");
+        assert!(line.backspace());
+        assert_eq!(line.as_bytes(), b"led 3n");
+        assert_eq!(line.erase_word(), 1);
+        assert_eq!(line.as_bytes(), b"led n");
+        line.end();
+        line.left();
+        assert_eq!(line.erase_to_start(), 4);
+        assert_eq!(line.as_bytes(), b"n");
+    }
+}
@@ -0,0 +1,98 @@
+use core::fmt::{self, Write};
+
+/// The characters used to end a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`, as used on Unix.
+    Lf,
+    /// `\r`, as sent by many serial terminals when Enter is pressed.
+    Cr,
+    /// `\r\n`, as used on Windows and by most raw serial terminals.
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            LineEnding::Lf => "\n",
+            LineEnding::Cr => "\r",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+/// Wraps the `Harness` writer, turning each `\n` written into the chosen
+/// line ending.
+pub(crate) struct Output<W> {
+    pub inner: W,
+    pub ending: LineEnding,
+    /// Was the last character written a `\r`? If so, and we're using CRLF
+    /// line endings, a following `\n` is left alone.
+    after_cr: bool,
+}
+
+impl<W> Output<W> {
+    pub fn new(inner: W) -> Output<W> {
+        Output {
+            inner,
+            ending: LineEnding::Lf,
+            after_cr: false,
+        }
+    }
+}
+
+impl<W> Write for Output<W>
+    where W: Write
+{
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.ending == LineEnding::Lf {
+            return self.inner.write_str(s);
+        }
+        for (i, piece) in s.split('\n').enumerate() {
+            if i > 0 {
+                if self.after_cr && self.ending == LineEnding::CrLf {
+                    self.inner.write_str("\n")?;
+                } else {
+                    self.inner.write_str(self.ending.as_str())?;
+                }
+                self.after_cr = false;
+            }
+            if !piece.is_empty() {
+                self.inner.write_str(piece)?;
+                self.after_cr = piece.ends_with('\r');
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{LineEnding, Output};
+    use core::fmt::Write;
+
+    #[test]
+    fn lf() {
+        let mut out = Output::new(String::new());
+        write!(out, "a\nb\r\n").unwrap();
+        assert_eq!(out.inner, "a\nb\r\n");
+    }
+
+    #[test]
+    fn crlf() {
+        let mut out = Output::new(String::new());
+        out.ending = LineEnding::CrLf;
+        write!(out, "a\nb\r\n\n").unwrap();
+        out.write_str("c\r").unwrap();
+        out.write_str("\nd").unwrap();
+        assert_eq!(out.inner, "a\r\nb\r\n\r\nc\r\nd");
+    }
+
+    #[test]
+    fn cr() {
+        let mut out = Output::new(String::new());
+        out.ending = LineEnding::Cr;
+        write!(out, "a\nb\n").unwrap();
+        assert_eq!(out.inner, "a\rb\r");
+    }
+}
@@ -0,0 +1,413 @@
+use core::fmt::{self, Write};
+
+use crate::{Error, Message};
+
+/// The kind of value a parameter takes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Kind<'a> {
+    /// A whole number between the limits, inclusive.
+    Int { min: i64, max: i64 },
+    /// A number between the limits, inclusive.
+    Float { min: f64, max: f64 },
+    /// `true`/`false`, `on`/`off`, `yes`/`no` or `1`/`0`.
+    Bool,
+    /// Any word at all.
+    Str,
+    /// One of the given words.
+    Enum(&'a [&'a str]),
+}
+
+/// Describes one of the arguments a command takes. If a command has any
+/// parameters, the `Harness` checks its arguments against them before
+/// calling the handler, and reports any mistakes along with a usage line.
+///
+/// Parameters are built with `const` functions, so a command's parameters
+/// can live in a `static` or `const`:
+///
+/// ```
+/// use harness::Param;
+///
+/// const PWM: [Param; 3] = [Param::int_range("channel", 0, 3),
+///                          Param::int_range("duty", 0, 100),
+///                          Param::choice("mode", &["fast", "slow"]).default("fast")];
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Param<'a> {
+    name: &'a str,
+    kind: Kind<'a>,
+    optional: bool,
+    default: Option<&'a str>,
+    help: &'a str,
+}
+
+impl<'a> Param<'a> {
+    const fn build(name: &'a str, kind: Kind<'a>) -> Param<'a> {
+        Param {
+            name,
+            kind,
+            optional: false,
+            default: None,
+            help: "",
+        }
+    }
+
+    /// A whole number.
+    pub const fn int(name: &'a str) -> Param<'a> {
+        Param::int_range(name, i64::MIN, i64::MAX)
+    }
+
+    /// A whole number from `min` to `max`, inclusive.
+    pub const fn int_range(name: &'a str, min: i64, max: i64) -> Param<'a> {
+        Param::build(name, Kind::Int { min, max })
+    }
+
+    /// A number, which may have a fractional part.
+    pub const fn float(name: &'a str) -> Param<'a> {
+        Param::float_range(name, f64::NEG_INFINITY, f64::INFINITY)
+    }
+
+    /// A number from `min` to `max`, inclusive.
+    pub const fn float_range(name: &'a str, min: f64, max: f64) -> Param<'a> {
+        Param::build(name, Kind::Float { min, max })
+    }
+
+    /// `true` or `false`. `on`/`off`, `yes`/`no` and `1`/`0` are accepted
+    /// too.
+    pub const fn bool(name: &'a str) -> Param<'a> {
+        Param::build(name, Kind::Bool)
+    }
+
+    /// Any word.
+    pub const fn string(name: &'a str) -> Param<'a> {
+        Param::build(name, Kind::Str)
+    }
+
+    /// One of the given words.
+    pub const fn choice(name: &'a str, choices: &'a [&'a str]) -> Param<'a> {
+        Param::build(name, Kind::Enum(choices))
+    }
+
+    /// Allows the argument to be left out. Only the last parameters of a
+    /// command may be optional.
+    pub const fn optional(mut self) -> Param<'a> {
+        self.optional = true;
+        self
+    }
+
+    /// Makes the argument optional, and gives the value the handler sees if
+    /// it is left out.
+    pub const fn default(mut self, value: &'a str) -> Param<'a> {
+        self.optional = true;
+        self.default = Some(value);
+        self
+    }
+
+    /// Describes the argument, for `help <command>`.
+    pub const fn help(mut self, help: &'a str) -> Param<'a> {
+        self.help = help;
+        self
+    }
+
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub fn is_optional(&self) -> bool {
+        self.optional
+    }
+
+    pub(crate) fn default_value(&self) -> Option<&'a str> {
+        self.default
+    }
+
+    /// Offers the values this parameter can take, if there are only a few.
+    pub(crate) fn complete(&self, add: &mut dyn FnMut(&str)) {
+        match self.kind {
+            Kind::Bool => ["true", "false"].iter().for_each(|s| add(s)),
+            Kind::Enum(choices) => choices.iter().for_each(|s| add(s)),
+            _ => {}
+        }
+    }
+
+    /// The value as the handler should see it: for a choice, the choice
+    /// it matches (which differs if case is ignored).
+    pub(crate) fn canonical<'v>(&self, value: &'v str) -> &'v str
+        where 'a: 'v
+    {
+        match self.kind {
+            Kind::Enum(choices) => choices
+                .iter()
+                .find(|c| **c == value)
+                .or_else(|| choices.iter().find(|c| c.eq_ignore_ascii_case(value)))
+                .map_or(value, |c| c),
+            _ => value,
+        }
+    }
+
+    fn check(&self, value: &str, ignore_case: bool) -> Result<(), Problem<'a>> {
+        match self.kind {
+            Kind::Int { min, max } => {
+                let n: i64 = value.parse().map_err(|_| Problem::NotInt)?;
+                if n < min || n > max {
+                    return Err(Problem::IntRange(min, max));
+                }
+            }
+            Kind::Float { min, max } => {
+                let n: f64 = value.parse().map_err(|_| Problem::NotFloat)?;
+                if n.is_nan() || n < min || n > max {
+                    return Err(Problem::FloatRange(min, max));
+                }
+            }
+            Kind::Bool => {
+                parse_bool(value).ok_or(Problem::NotBool)?;
+            }
+            Kind::Str => {}
+            Kind::Enum(choices) => {
+                let matches = |choice: &&str| {
+                    *choice == value || (ignore_case && choice.eq_ignore_ascii_case(value))
+                };
+                if !choices.iter().any(matches) {
+                    return Err(Problem::NotChoice(choices));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" | "on" | "yes" | "1" => Some(true),
+        "false" | "off" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// What is wrong with an argument.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Problem<'a> {
+    Missing,
+    Unexpected,
+    NotInt,
+    NotFloat,
+    NotBool,
+    NotChoice(&'a [&'a str]),
+    IntRange(i64, i64),
+    FloatRange(f64, f64),
+}
+
+/// An argument that doesn't fit a command's parameters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ParamError<'a, 'v> {
+    /// Which argument it is, counting from 1.
+    index: usize,
+    name: Option<&'a str>,
+    value: &'v str,
+    problem: Problem<'a>,
+}
+
+impl<'a, 'v> fmt::Display for ParamError<'a, 'v> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "arg {}", self.index)?;
+        if let Some(name) = self.name {
+            write!(f, " ({})", name)?;
+        }
+        f.write_str(": ")?;
+        let value = self.value;
+        match self.problem {
+            Problem::Missing => f.write_str("missing"),
+            Problem::Unexpected => write!(f, "unexpected argument {}", value),
+            Problem::NotInt => write!(f, "{} is not an integer", value),
+            Problem::NotFloat => write!(f, "{} is not a number", value),
+            Problem::NotBool => write!(f, "{} is not true or false", value),
+            Problem::NotChoice(choices) => {
+                write!(f, "{} is not one of ", value)?;
+                for (i, choice) in choices.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    f.write_str(choice)?;
+                }
+                Ok(())
+            }
+            Problem::IntRange(min, max) => write!(f, "{} out of range {}..={}", value, min, max),
+            Problem::FloatRange(min, max) => write!(f, "{} out of range {}..={}", value, min, max),
+        }
+    }
+}
+
+/// Checks a command's arguments against its parameters. If `ignore_case` is
+/// set, the case of ASCII letters in a choice doesn't matter.
+pub(crate) fn check<'a, 'v>(params: &[Param<'a>],
+                            args: &[&'v str],
+                            ignore_case: bool)
+                            -> Result<(), ParamError<'a, 'v>> {
+    for (i, value) in args.iter().enumerate() {
+        let error = |name, problem| ParamError { index: i + 1, name, value, problem };
+        let param = params.get(i).ok_or_else(|| error(None, Problem::Unexpected))?;
+        param.check(value, ignore_case).map_err(|problem| error(Some(param.name), problem))?;
+    }
+    match params.iter().enumerate().skip(args.len()).find(|(_, p)| !p.optional) {
+        Some((i, param)) => Err(ParamError {
+            index: i + 1,
+            name: Some(param.name),
+            value: "",
+            problem: Problem::Missing,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Says what values a parameter takes, e.g. `0..=100; default 50`.
+struct Summary<'p, 'a>(&'p Param<'a>);
+
+impl<'p, 'a> Summary<'p, 'a> {
+    fn is_empty(&self) -> bool {
+        self.0.kind == Kind::Str && !self.0.optional
+    }
+}
+
+impl<'p, 'a> fmt::Display for Summary<'p, 'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let param = self.0;
+        match param.kind {
+            Kind::Int { min: i64::MIN, max: i64::MAX } => f.write_str("integer")?,
+            Kind::Int { min, max } => write!(f, "{}..={}", min, max)?,
+            Kind::Float { min, max } if min == f64::NEG_INFINITY && max == f64::INFINITY => {
+                f.write_str("number")?
+            }
+            Kind::Float { min, max } => write!(f, "{}..={}", min, max)?,
+            Kind::Bool => f.write_str("true or false")?,
+            Kind::Str => {}
+            Kind::Enum(choices) => {
+                f.write_str("one of ")?;
+                for (i, choice) in choices.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    f.write_str(choice)?;
+                }
+            }
+        }
+        let sep = if param.kind == Kind::Str { "" } else { "; " };
+        match param.default {
+            Some(value) => write!(f, "{}default {}", sep, value),
+            None if param.optional => write!(f, "{}optional", sep),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Writes a line for each parameter, giving its name, description and what
+/// values it takes.
+pub(crate) fn write_params(out: &mut dyn Write, params: &[Param]) -> fmt::Result {
+    let width = params.iter().map(|p| p.name.chars().count()).max().unwrap_or(0);
+    for param in params {
+        write!(out, "  {:width$}  ", param.name, width = width)?;
+        let summary = Summary(param);
+        if param.help.is_empty() {
+            writeln!(out, "{}", summary)?;
+        } else if summary.is_empty() {
+            writeln!(out, "{}", param.help)?;
+        } else {
+            writeln!(out, "{} ({})", param.help, summary)?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks the arguments given to the command at `path`, describing any
+/// problem in an `Error::InvalidArgument`.
+pub(crate) fn validate(params: &[Param],
+                       args: &[&str],
+                       path: &[&str],
+                       ignore_case: bool)
+                       -> Result<(), Error> {
+    check(params, args, ignore_case).map_err(|e| {
+        let mut usage = Message::new();
+        let _ = write_usage(&mut usage, path, params);
+        Error::InvalidArgument { problem: Message::format(e), usage }
+    })
+}
+
+/// Writes something like `Usage: pwm <channel> <duty> [mode]`.
+pub(crate) fn write_usage(out: &mut dyn Write, path: &[&str], params: &[Param]) -> fmt::Result {
+    out.write_str("Usage:")?;
+    for word in path {
+        write!(out, " {}", word)?;
+    }
+    for param in params {
+        if param.optional {
+            write!(out, " [{}]", param.name)?;
+        } else {
+            write!(out, " <{}>", param.name)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{check, write_params, write_usage, Param};
+
+    const PWM: [Param; 3] = [Param::int_range("channel", 0, 3),
+                             Param::int_range("duty", 0, 100),
+                             Param::choice("mode", &["fast", "slow"]).default("fast")];
+
+    fn error(params: &[Param], args: &[&str]) -> String {
+        check(params, args, false).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn valid() {
+        assert_eq!(check(&PWM, &["1", "50"], false), Ok(()));
+        assert_eq!(check(&PWM, &["3", "0", "slow"], false), Ok(()));
+        assert_eq!(check(&PWM, &["3", "0", "Slow"], true), Ok(()));
+        assert_eq!(PWM[2].canonical("SLOW"), "slow");
+        assert_eq!(PWM[1].canonical("50"), "50");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error(&PWM, &["1", "150"]), "arg 2 (duty): 150 out of range 0..=100");
+        assert_eq!(error(&PWM, &["x", "150"]), "arg 1 (channel): x is not an integer");
+        assert_eq!(error(&PWM, &["1"]), "arg 2 (duty): missing");
+        assert_eq!(error(&PWM, &["1", "2", "medium"]),
+                   "arg 3 (mode): medium is not one of fast, slow");
+        assert_eq!(error(&PWM, &["1", "2", "FAST"]), "arg 3 (mode): FAST is not one of fast, slow");
+        assert_eq!(error(&PWM, &["1", "2", "fast", "now"]), "arg 4: unexpected argument now");
+    }
+
+    #[test]
+    fn kinds() {
+        let params = [Param::float_range("volts", 0.0, 3.3), Param::bool("on"), Param::string("s").optional()];
+        assert_eq!(check(&params, &["1.5", "on"], false), Ok(()));
+        assert_eq!(check(&params, &["3.3", "0", "x"], false), Ok(()));
+        assert_eq!(error(&params, &["3.4", "on"]), "arg 1 (volts): 3.4 out of range 0..=3.3");
+        assert_eq!(error(&params, &["NaN", "on"]), "arg 1 (volts): NaN out of range 0..=3.3");
+        assert_eq!(error(&params, &["1e", "on"]), "arg 1 (volts): 1e is not a number");
+        assert_eq!(error(&params, &["1", "maybe"]), "arg 2 (on): maybe is not true or false");
+    }
+
+    #[test]
+    fn summaries() {
+        let params = [Param::int("n").help("How many"),
+                      Param::float_range("volts", 0.0, 3.3),
+                      Param::bool("on").help("Power"),
+                      Param::string("label").help("A name"),
+                      Param::string("note").optional(),
+                      Param::choice("mode", &["fast", "slow"]).default("fast")];
+        let mut out = String::new();
+        write_params(&mut out, &params).unwrap();
+        assert_eq!(out,
+                   "  n      How many (integer)\n  volts  0..=3.3\n  on     Power (true or false)\n  \
+                    label  A name\n  note   optional\n  mode   one of fast, slow; default fast\n");
+    }
+
+    #[test]
+    fn usage() {
+        let mut out = String::new();
+        write_usage(&mut out, &["led", "pwm"], &PWM).unwrap();
+        assert_eq!(out, "Usage: led pwm <channel> <duty> [mode]");
+    }
+}
@@ -0,0 +1,156 @@
+use core::fmt::{self, Write};
+
+use crate::{Error, Outcome};
+
+/// How deeply the `source` built-in command can nest scripts.
+pub(crate) const MAX_DEPTH: usize = 8;
+
+/// What a script does when one of its commands fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnError {
+    /// Stop running the script.
+    Stop,
+    /// Print the error and carry on with the next line.
+    Continue,
+}
+
+/// A command in a script that failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    /// The line the command is on, counting from 1.
+    pub line: usize,
+    pub error: Error,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ScriptError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Looks up the scripts that the `source` built-in command runs (see
+/// `Harness::set_scripts`).
+///
+/// An array or slice of `(name, script)` pairs is a `Scripts`:
+///
+/// ```
+/// use harness::Scripts;
+///
+/// static SCRIPTS: [(&str, &str); 1] = [("boot", "led 0 on\n# Wait for the PLL\npll lock\n")];
+/// assert_eq!(SCRIPTS.script("boot"), Some("led 0 on\n# Wait for the PLL\npll lock\n"));
+/// ```
+pub trait Scripts {
+    /// The script called `name`, if there is one.
+    fn script(&self, name: &str) -> Option<&str>;
+}
+
+impl<'s> Scripts for [(&'s str, &'s str)] {
+    fn script(&self, name: &str) -> Option<&str> {
+        self.iter().find(|(n, _)| *n == name).map(|(_, script)| *script)
+    }
+}
+
+impl<'s, const N: usize> Scripts for [(&'s str, &'s str); N] {
+    fn script(&self, name: &str) -> Option<&str> {
+        self[..].script(name)
+    }
+}
+
+/// The command on a line of a script, or `None` if the line is blank or a
+/// comment.
+pub(crate) fn command(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        None
+    } else {
+        Some(line)
+    }
+}
+
+/// Keeps track of a script as it runs.
+pub(crate) struct Run {
+    on_error: OnError,
+    /// The first command to fail, if the script carries on regardless.
+    failed: Option<ScriptError>,
+}
+
+impl Run {
+    pub fn new(on_error: OnError) -> Run {
+        Run { on_error, failed: None }
+    }
+
+    /// Takes note of the result of the command on `line`. Returns what the
+    /// script as a whole returns, if it should stop now. Errors are written
+    /// to `out` if the script carries on.
+    pub fn step(&mut self,
+                line: usize,
+                result: Result<Outcome, Error>,
+                out: &mut dyn Write)
+                -> Option<Result<Outcome, ScriptError>> {
+        match result {
+            Ok(Outcome::Continue) => None,
+            Ok(outcome) => Some(Ok(outcome)),
+            Err(error) => {
+                let error = ScriptError { line, error };
+                match self.on_error {
+                    OnError::Stop => Some(Err(error)),
+                    OnError::Continue => {
+                        let _ = writeln!(out, "Error: {}", error);
+                        self.failed.get_or_insert(error);
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    /// What the script returns once every line has been run.
+    pub fn finish(self) -> Result<Outcome, ScriptError> {
+        match self.failed {
+            Some(error) => Err(error),
+            None => Ok(Outcome::Continue),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{command, OnError, Run, ScriptError};
+    use crate::{Error, Outcome};
+
+    #[test]
+    fn commands() {
+        assert_eq!(command("  led 0 on \r"), Some("led 0 on"));
+        assert_eq!(command("   "), None);
+        assert_eq!(command("  # led 0 on"), None);
+    }
+
+    #[test]
+    fn carry_on() {
+        let mut out = String::new();
+        let mut run = Run::new(OnError::Continue);
+        assert_eq!(run.step(1, Err("boom".into()), &mut out), None);
+        assert_eq!(run.step(2, Err("bang".into()), &mut out), None);
+        assert_eq!(run.step(3, Ok(Outcome::Continue), &mut out), None);
+        assert_eq!(out, "Error: line 1: boom\nError: line 2: bang\n");
+        assert_eq!(run.finish(), Err(ScriptError { line: 1, error: Error::from("boom") }));
+    }
+
+    #[test]
+    fn stop() {
+        let mut out = String::new();
+        let mut run = Run::new(OnError::Stop);
+        assert_eq!(run.step(1, Ok(Outcome::Continue), &mut out), None);
+        assert_eq!(run.step(2, Err("boom".into()), &mut out),
+                   Some(Err(ScriptError { line: 2, error: Error::from("boom") })));
+        assert_eq!(run.step(3, Ok(Outcome::EndSession), &mut out), Some(Ok(Outcome::EndSession)));
+        assert_eq!(out, "");
+    }
+}
@@ -0,0 +1,184 @@
+use core::fmt;
+use core::iter::FromIterator;
+
+use crate::Message;
+
+/// How many names are suggested, at most.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// The longest name, in characters, that is compared. Longer names are
+/// never suggested.
+const MAX_NAME: usize = 32;
+
+/// The names of commands that are close to a word which isn't one, closest
+/// first. Returned in `Error::UnknownCommand` and
+/// `Error::UnknownSubcommand`.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Suggestions {
+    /// The names, separated by spaces.
+    names: Message,
+}
+
+impl Suggestions {
+    pub fn new() -> Suggestions {
+        Suggestions { names: Message::new() }
+    }
+
+    /// The names suggested, closest first.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.names.as_str().split_whitespace()
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.as_str().is_empty()
+    }
+
+    /// Adds a name, unless there is no room left for the whole of it.
+    fn push(&mut self, name: &str) {
+        let sep = if self.is_empty() { "" } else { " " };
+        if sep.len() + name.len() <= self.names.remaining() {
+            let _ = fmt::Write::write_fmt(&mut self.names, format_args!("{}{}", sep, name));
+        }
+    }
+}
+
+impl<'n> FromIterator<&'n str> for Suggestions {
+    fn from_iter<I: IntoIterator<Item = &'n str>>(names: I) -> Suggestions {
+        let mut suggestions = Suggestions::new();
+        names.into_iter().for_each(|name| suggestions.push(name));
+        suggestions
+    }
+}
+
+impl fmt::Debug for Suggestions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// Writes e.g. `'start', 'status' or 'stop'`.
+impl fmt::Display for Suggestions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let len = self.len();
+        for (i, name) in self.iter().enumerate() {
+            match i {
+                0 => {}
+                _ if i + 1 == len => f.write_str(" or ")?,
+                _ => f.write_str(", ")?,
+            }
+            write!(f, "'{}'", name)?;
+        }
+        Ok(())
+    }
+}
+
+/// The names (up to three) that are no more than `threshold` edits away
+/// from `word`, closest first and then in alphabetical order. An edit is
+/// inserting, removing or changing one character, or swapping two adjacent
+/// ones. Names as far from `word` as it is long are not suggested, so `x`
+/// doesn't suggest every two-letter command. A `threshold` of 0 suggests
+/// nothing. If `ignore_case` is set, changing the case of an ASCII letter
+/// isn't an edit.
+pub(crate) fn suggest<'n, I>(word: &str, names: I, threshold: usize, ignore_case: bool) -> Suggestions
+    where I: Iterator<Item = &'n str>
+{
+    let limit = threshold.min(word.chars().count().saturating_sub(1));
+    let mut best: [(usize, &str); MAX_SUGGESTIONS] = [(usize::MAX, ""); MAX_SUGGESTIONS];
+    for name in names {
+        if best.iter().any(|(_, b)| *b == name) {
+            continue;
+        }
+        let d = match distance(word, name, limit, ignore_case) {
+            Some(d) => d,
+            None => continue,
+        };
+        let worst = &mut best[MAX_SUGGESTIONS - 1];
+        if (d, name) < *worst {
+            *worst = (d, name);
+            best.sort_unstable();
+        }
+    }
+    best.iter().filter(|(d, _)| *d != usize::MAX).map(|(_, name)| *name).collect()
+}
+
+/// The number of edits (see `suggest`) needed to turn `a` into `b`, if it is
+/// no more than `limit`.
+fn distance(a: &str, b: &str, limit: usize, ignore_case: bool) -> Option<usize> {
+    let fold = |c: char| if ignore_case { c.to_ascii_lowercase() } else { c };
+    let a_len = a.chars().count();
+    let b_len = b.chars().count();
+    if a_len > MAX_NAME || b_len > MAX_NAME || a_len.abs_diff(b_len) > limit {
+        return None;
+    }
+    // Three rows of the table: for the previous two characters of `a`, and
+    // this one. Each holds the distance from that much of `a` to each prefix
+    // of `b`.
+    let mut before = [0usize; MAX_NAME + 1];
+    let mut previous = [0usize; MAX_NAME + 1];
+    let mut row = [0usize; MAX_NAME + 1];
+    for (j, cell) in previous.iter_mut().enumerate() {
+        *cell = j;
+    }
+    let mut last_a = None;
+    for (i, ca) in a.chars().map(fold).enumerate() {
+        row[0] = i + 1;
+        let mut last_b = None;
+        for (j, cb) in b.chars().map(fold).enumerate() {
+            let cost = usize::from(ca != cb);
+            let mut d = (previous[j] + cost).min(previous[j + 1] + 1).min(row[j] + 1);
+            if last_a == Some(cb) && last_b == Some(ca) {
+                d = d.min(before[j - 1] + 1);
+            }
+            row[j + 1] = d;
+            last_b = Some(cb);
+        }
+        before = previous;
+        previous = row;
+        last_a = Some(ca);
+    }
+    Some(previous[b_len]).filter(|d| *d <= limit)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{distance, suggest, Suggestions};
+
+    #[test]
+    fn distances() {
+        assert_eq!(distance("status", "status", 2, false), Some(0));
+        assert_eq!(distance("stauts", "status", 2, false), Some(1));
+        assert_eq!(distance("stat", "status", 2, false), Some(2));
+        assert_eq!(distance("sta", "status", 2, false), None);
+        assert_eq!(distance("rest", "reset", 2, false), Some(1));
+        assert_eq!(distance("ab", "ba", 1, false), Some(1));
+        assert_eq!(distance("", "ab", 2, false), Some(2));
+        assert_eq!(distance("STAUTS", "status", 2, false), None);
+        assert_eq!(distance("STAUTS", "status", 2, true), Some(1));
+    }
+
+    #[test]
+    fn suggestions() {
+        let names = ["start", "status", "stop", "reset", "help"];
+        let found = suggest("stauts", names.iter().copied(), 2, false);
+        assert_eq!(found.iter().collect::<Vec<_>>(), ["status", "start"]);
+        let found = suggest("stap", names.iter().copied(), 2, false);
+        assert_eq!(found.iter().collect::<Vec<_>>(), ["stop", "start"]);
+        let found = suggest("stat", names.iter().copied(), 2, false);
+        assert_eq!(found.to_string(), "'start', 'status' or 'stop'");
+        assert!(suggest("stap", names.iter().copied(), 0, false).is_empty());
+        assert!(suggest("x", ["ls"].iter().copied(), 2, false).is_empty());
+    }
+
+    #[test]
+    fn display() {
+        let one: Suggestions = ["status"].iter().copied().collect();
+        assert_eq!(one.to_string(), "'status'");
+        let two: Suggestions = ["start", "stop"].iter().copied().collect();
+        assert_eq!(two.to_string(), "'start' or 'stop'");
+        assert_eq!(format!("{:?}", two), r#"["start", "stop"]"#);
+    }
+}
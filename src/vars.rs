@@ -0,0 +1,275 @@
+use core::str;
+
+use crate::Error;
+
+/// How many variables a session can hold.
+pub(crate) const MAX_VARIABLES: usize = 8;
+
+/// The longest value a variable can hold, in bytes.
+pub(crate) const MAX_VALUE: usize = 32;
+
+/// How many macros a session can hold.
+pub(crate) const MAX_MACROS: usize = 8;
+
+/// The longest command line a macro can run, in bytes.
+pub(crate) const MAX_BODY: usize = 64;
+
+/// The longest name of a variable or macro, in bytes.
+pub(crate) const MAX_NAME: usize = 16;
+
+/// The variables set in a session, which `$name` refers to.
+pub(crate) type Variables = Store<MAX_VALUE, MAX_VARIABLES>;
+
+/// The macros defined in a session, as `(name, command line)`.
+pub(crate) type Macros = Store<MAX_BODY, MAX_MACROS>;
+
+#[derive(Clone, Copy)]
+struct Slot<const LEN: usize> {
+    name: [u8; MAX_NAME],
+    /// 0 if the slot is free.
+    name_len: u8,
+    value: [u8; LEN],
+    value_len: u8,
+}
+
+impl<const LEN: usize> Slot<LEN> {
+    const EMPTY: Slot<LEN> = Slot {
+        name: [0; MAX_NAME],
+        name_len: 0,
+        value: [0; LEN],
+        value_len: 0,
+    };
+
+    fn name(&self) -> &str {
+        str::from_utf8(&self.name[..usize::from(self.name_len)]).unwrap_or("")
+    }
+
+    fn value(&self) -> &str {
+        str::from_utf8(&self.value[..usize::from(self.value_len)]).unwrap_or("")
+    }
+}
+
+/// Up to `COUNT` named values, each up to `LEN` bytes long. It needs no
+/// allocator.
+pub(crate) struct Store<const LEN: usize, const COUNT: usize> {
+    slots: [Slot<LEN>; COUNT],
+    /// What the values are, e.g. `variable`, for error messages.
+    kind: &'static str,
+}
+
+impl<const LEN: usize, const COUNT: usize> Store<LEN, COUNT> {
+    pub const fn new(kind: &'static str) -> Store<LEN, COUNT> {
+        Store { slots: [Slot::EMPTY; COUNT], kind }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.position(name).map(|idx| self.slots[idx].value())
+    }
+
+    /// Where the value called `name` is kept. This doesn't change until
+    /// it is unset.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.slots.iter().position(|s| s.name_len != 0 && s.name() == name)
+    }
+
+    /// The name and value at `idx`, which came from `position`.
+    pub fn at(&self, idx: usize) -> (&str, &str) {
+        (self.slots[idx].name(), self.slots[idx].value())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.iter().all(|s| s.name_len == 0)
+    }
+
+    /// Sets a value, replacing any it already has.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        self.set_words(name, &[value])
+    }
+
+    /// Sets a value to `words` joined with spaces, replacing any it already
+    /// has.
+    pub fn set_words(&mut self, name: &str, words: &[&str]) -> Result<(), Error> {
+        if !is_name(name) {
+            return Err(Error::failed(format_args!("'{}' isn't a valid {} name", name, self.kind)));
+        }
+        if name.len() > MAX_NAME {
+            return Err(Error::failed(format_args!("{} names are at most {} bytes", self.kind, MAX_NAME)));
+        }
+        let len = words.iter().map(|word| word.len()).sum::<usize>() + words.len().saturating_sub(1);
+        if len > LEN {
+            return Err(Error::failed(format_args!("too long for a {}, which holds at most {} bytes",
+                                                  self.kind,
+                                                  LEN)));
+        }
+        let idx = match self.position(name).or_else(|| self.slots.iter().position(|s| s.name_len == 0)) {
+            Some(idx) => idx,
+            None => return Err(Error::failed(format_args!("no room for more than {} {}s", COUNT, self.kind))),
+        };
+        let slot = &mut self.slots[idx];
+        slot.name[..name.len()].copy_from_slice(name.as_bytes());
+        slot.name_len = name.len() as u8;
+        let mut end = 0;
+        for (i, word) in words.iter().enumerate() {
+            if i > 0 {
+                slot.value[end] = b' ';
+                end += 1;
+            }
+            slot.value[end..end + word.len()].copy_from_slice(word.as_bytes());
+            end += word.len();
+        }
+        slot.value_len = len as u8;
+        Ok(())
+    }
+
+    /// Removes a value. Returns whether it was set.
+    pub fn unset(&mut self, name: &str) -> bool {
+        match self.position(name) {
+            Some(idx) => {
+                self.slots[idx] = Slot::EMPTY;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Each name and value, in alphabetical order of name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> + Clone {
+        let mut sorted: [Option<&Slot<LEN>>; COUNT] = [None; COUNT];
+        for (entry, slot) in sorted.iter_mut().zip(self.slots.iter().filter(|s| s.name_len != 0)) {
+            *entry = Some(slot);
+        }
+        sorted.sort_unstable_by_key(|slot| slot.map(Slot::name));
+        IntoIterator::into_iter(sorted).flatten().map(|s| (s.name(), s.value()))
+    }
+}
+
+/// What `$name` refers to when a command line is split into words.
+pub(crate) struct Scope<'v> {
+    pub variables: &'v Variables,
+    /// Whether the last command failed, which is what `$?` says.
+    pub failed: bool,
+    /// The arguments given to the macro being run, which `$1`, `$2` and so
+    /// on refer to.
+    pub args: &'v [&'v str],
+}
+
+impl<'v> Scope<'v> {
+    /// The value of a variable, or `None` if it isn't set.
+    pub fn get(&self, name: &str) -> Option<&'v str> {
+        match name {
+            "?" => Some(if self.failed { "1" } else { "0" }),
+            _ => {
+                match name.parse::<usize>() {
+                    Ok(n) => self.args.get(n.checked_sub(1)?).copied(),
+                    Err(_) => self.variables.get(name),
+                }
+            }
+        }
+    }
+}
+
+/// Whether `name` can be the name of a variable: a letter or underscore,
+/// followed by letters, digits and underscores.
+fn is_name(name: &str) -> bool {
+    let mut bytes = name.bytes();
+    matches!(bytes.next(), Some(b'a'..=b'z' | b'A'..=b'Z' | b'_'))
+        && bytes.all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+/// Whether `name` is the number of a macro argument.
+fn is_number(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// The name referred to at the start of `src`, which follows a `$`, and how
+/// many bytes of `src` refer to it. It is either `?`, a digit, a name, or a
+/// name or number in braces. Returns `None` if there is no name, in which
+/// case the `$` is just a `$`.
+pub(crate) fn reference(src: &str) -> Option<(&str, usize)> {
+    match src.as_bytes().first() {
+        Some(b'?') | Some(b'0'..=b'9') => return Some((&src[..1], 1)),
+        _ => {}
+    }
+    if let Some(rest) = src.strip_prefix('{') {
+        let end = rest.find('}')?;
+        let name = &rest[..end];
+        return (is_name(name) || is_number(name)).then_some((name, end + 2));
+    }
+    let end = src.bytes().position(|b| !(b.is_ascii_alphanumeric() || b == b'_')).unwrap_or(src.len());
+    let name = &src[..end];
+    is_name(name).then_some((name, end))
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{reference, Macros, Scope, Variables, MAX_VARIABLES};
+
+    #[test]
+    fn store() {
+        let mut vars = Variables::new("variable");
+        assert_eq!(vars.get("addr"), None);
+        assert!(vars.is_empty());
+        vars.set("addr", "0x4000").unwrap();
+        vars.set("count", "3").unwrap();
+        vars.set("addr", "0x8000").unwrap();
+        assert_eq!(vars.get("addr"), Some("0x8000"));
+        assert_eq!(vars.iter().collect::<Vec<_>>(), [("addr", "0x8000"), ("count", "3")]);
+        assert!(vars.unset("addr"));
+        assert!(!vars.unset("addr"));
+        assert_eq!(vars.iter().collect::<Vec<_>>(), [("count", "3")]);
+    }
+
+    #[test]
+    fn limits() {
+        let mut vars = Variables::new("variable");
+        assert_eq!(vars.set("1x", "").unwrap_err().to_string(), "'1x' isn't a valid variable name");
+        assert_eq!(vars.set("", "").unwrap_err().to_string(), "'' isn't a valid variable name");
+        assert!(vars.set("a_very_long_variable", "").is_err());
+        assert_eq!(vars.set("x", &"y".repeat(33)).unwrap_err().to_string(),
+                   "too long for a variable, which holds at most 32 bytes");
+        for i in 0..MAX_VARIABLES {
+            vars.set(&format!("v{}", i), "").unwrap();
+        }
+        assert_eq!(vars.set("more", "").unwrap_err().to_string(), "no room for more than 8 variables");
+        vars.set("v0", "changed").unwrap();
+    }
+
+    #[test]
+    fn words() {
+        let mut macros = Macros::new("macro");
+        macros.set_words("calib", &["adc", "reset;", "adc", "cal", "3"]).unwrap();
+        assert_eq!(macros.get("calib"), Some("adc reset; adc cal 3"));
+        macros.set_words("none", &[]).unwrap();
+        assert_eq!(macros.get("none"), Some(""));
+        assert_eq!(macros.position("calib"), Some(0));
+        assert_eq!(macros.set_words("x", &["y"; 33]).unwrap_err().to_string(),
+                   "too long for a macro, which holds at most 64 bytes");
+        assert_eq!(macros.set_words("x", &["y"; 32]), Ok(()));
+    }
+
+    #[test]
+    fn references() {
+        assert_eq!(reference("addr/2"), Some(("addr", 4)));
+        assert_eq!(reference("{addr}ess"), Some(("addr", 6)));
+        assert_eq!(reference("?x"), Some(("?", 1)));
+        assert_eq!(reference("{addr"), None);
+        assert_eq!(reference("{}"), None);
+        assert_eq!(reference(" x"), None);
+        assert_eq!(reference("12"), Some(("1", 1)));
+        assert_eq!(reference("{12}"), Some(("12", 4)));
+        assert_eq!(reference(""), None);
+    }
+
+    #[test]
+    fn scope() {
+        let mut vars = Variables::new("variable");
+        vars.set("x", "1").unwrap();
+        let scope = Scope { variables: &vars, failed: true, args: &["a", "b"] };
+        assert_eq!(scope.get("?"), Some("1"));
+        assert_eq!(scope.get("x"), Some("1"));
+        assert_eq!(scope.get("y"), None);
+        assert_eq!(scope.get("2"), Some("b"));
+        assert_eq!(scope.get("0"), None);
+        assert_eq!(scope.get("3"), None);
+    }
+}
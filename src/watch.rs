@@ -0,0 +1,97 @@
+use crate::{Error, MAX_ARGS};
+
+/// Tells the time, so that the `watch` built-in command can run a command
+/// every so often (see `Harness::set_clock`). On an embedded system, this
+/// might read a timer that ticks once a millisecond.
+pub trait Clock {
+    /// The number of milliseconds since some fixed point, such as when the
+    /// system started. It may wrap around.
+    fn now_ms(&self) -> u64;
+}
+
+/// A command that the `watch` built-in command runs every `interval`
+/// milliseconds, along with when it last ran.
+#[derive(Clone, Copy)]
+pub(crate) struct Watch<const N: usize> {
+    /// The words of the command, one after another.
+    words: [u8; N],
+    /// Where each word ends in `words`.
+    ends: [usize; MAX_ARGS],
+    count: usize,
+    /// How often the command runs, in seconds, as the user gave it.
+    pub seconds: f64,
+    interval: u64,
+    last: u64,
+}
+
+impl<const N: usize> Watch<N> {
+    /// Watches the command `argv`, which runs for the first time at `now`.
+    pub fn new(argv: &[&str], seconds: f64, now: u64) -> Result<Watch<N>, Error> {
+        let mut watch = Watch {
+            words: [0; N],
+            ends: [0; MAX_ARGS],
+            count: 0,
+            seconds,
+            interval: (seconds.max(0.0) * 1000.0) as u64,
+            last: now,
+        };
+        let mut len = 0;
+        for word in argv.iter().take(MAX_ARGS) {
+            let end = len + word.len();
+            watch.words.get_mut(len..end).ok_or(Error::LineTooLong)?.copy_from_slice(word.as_bytes());
+            len = end;
+            watch.ends[watch.count] = len;
+            watch.count += 1;
+        }
+        Ok(watch)
+    }
+
+    /// Splits the command back into words, in `argv`. Returns how many
+    /// there are.
+    pub fn argv<'w>(&'w self, argv: &mut [&'w str; MAX_ARGS]) -> usize {
+        let mut start = 0;
+        for (arg, &end) in argv.iter_mut().zip(&self.ends[..self.count]) {
+            *arg = core::str::from_utf8(&self.words[start..end]).unwrap_or_default();
+            start = end;
+        }
+        self.count
+    }
+
+    /// Whether it is time to run the command again. If it is, the command
+    /// is taken to have run at `now`.
+    pub fn due(&mut self, now: u64) -> bool {
+        if now.wrapping_sub(self.last) < self.interval {
+            return false;
+        }
+        self.last = now;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Watch;
+    use crate::{Error, MAX_ARGS};
+
+    #[test]
+    fn words() {
+        let watch: Watch<16> = Watch::new(&["adc", "read", "", "two words"], 1.0, 0).unwrap();
+        let mut argv = [""; MAX_ARGS];
+        let count = watch.argv(&mut argv);
+        assert_eq!(&argv[..count], ["adc", "read", "", "two words"]);
+        assert!(matches!(Watch::<8>::new(&["status", "all"], 1.0, 0), Err(Error::LineTooLong)));
+    }
+
+    #[test]
+    fn timing() {
+        let mut watch: Watch<16> = Watch::new(&["status"], 0.5, 1000).unwrap();
+        assert!(!watch.due(1000));
+        assert!(!watch.due(1499));
+        assert!(watch.due(1500));
+        assert!(!watch.due(1999));
+        assert!(watch.due(2100));
+        let mut wrapping: Watch<16> = Watch::new(&["status"], 1.0, u64::MAX - 100).unwrap();
+        assert!(!wrapping.due(500));
+        assert!(wrapping.due(900));
+    }
+}
@@ -0,0 +1,91 @@
+use core::fmt;
+use std::io;
+
+/// Adapts a `std::io::Write` (such as `std::io::stdout()`) so that it can be
+/// used as the writer for a `Harness`.
+///
+/// The underlying writer is flushed after every write, so prompts and
+/// echoed characters appear immediately.
+///
+/// `core::fmt::Write` can't say why a write failed, so when the `Harness`
+/// returns an `Error::Io`, `take_error` gives the `std::io::Error` behind it.
+pub struct IoWriter<W> {
+    inner: W,
+    /// Why the last write failed, if it did.
+    error: Option<io::Error>,
+}
+
+impl<W> IoWriter<W>
+    where W: io::Write
+{
+    pub fn new(inner: W) -> IoWriter<W> {
+        IoWriter { inner, error: None }
+    }
+
+    /// Why the last failed write failed, if one has since it was last
+    /// called.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W> fmt::Write for IoWriter<W>
+    where W: io::Write
+{
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).and_then(|()| self.inner.flush()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IoWriter;
+    use crate::{Error, Harness};
+    use std::io;
+
+    /// Accepts `room` bytes, then fails.
+    struct Full {
+        room: usize,
+    }
+
+    impl io::Write for Full {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.room == 0 {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "terminal went away"));
+            }
+            let len = buf.len().min(self.room);
+            self.room -= len;
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn keeps_error() {
+        let mut h: Harness<_> = Harness::new(IoWriter::new(Full { room: 4 }));
+        assert!(h.writer_mut().take_error().is_none());
+        assert!(matches!(h.print_help_for(&["help"]), Err(Error::Io { context: "printing help", .. })));
+        let error = h.writer_mut().take_error().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(error.to_string(), "terminal went away");
+        assert!(h.writer_mut().take_error().is_none());
+    }
+}